@@ -1,6 +1,11 @@
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
 
-use potionforge::models::{traits::GetName, Process, INGREDIENTS};
+use potionforge::{
+    enumerate::{default_filters, EnumerateConfig},
+    models::{traits::GetName, Process, INGREDIENTS},
+    process::PROCESS_VARIANTS,
+    progress::{CancellationToken, NoProgress},
+};
 
 pub fn permute_ingredient(c: &mut Criterion) {
     let processes = vec![Process::Crush];
@@ -34,12 +39,42 @@ pub fn permute_ingredients(c: &mut Criterion) {
 
     c.bench_function("permute_ingredients", |b| {
         b.iter(|| {
-            let all_ingredients =
-                ::potionforge::enumerate::permute_ingredients(ingredients, &processes);
+            let all_ingredients = ::potionforge::enumerate::permute_ingredients(
+                &PROCESS_VARIANTS,
+                ingredients,
+                &processes,
+            );
             black_box(all_ingredients);
         });
     });
 }
 
-criterion_group!(benches, permute_ingredient, permute_ingredients);
+pub fn enumerate(c: &mut Criterion) {
+    let config = EnumerateConfig {
+        ingredients: INGREDIENTS.0.iter().map(|(key, _)| *key).collect(),
+        arcane_power: 3,
+        utilisation: 1,
+        processes: vec![
+            Process::Crush,
+            Process::Blanch,
+            Process::Dry,
+            Process::Pickle,
+        ],
+        repeated_ingredients: false,
+        filters: default_filters(),
+        variants: PROCESS_VARIANTS.clone(),
+    };
+    let cancel = CancellationToken::new();
+
+    let mut group = c.benchmark_group("enumerate");
+    group.sample_size(10);
+    group.bench_function("enumerate", |b| {
+        b.iter(|| {
+            let recipes = ::potionforge::core::enumerate(&config, &NoProgress, &cancel).unwrap();
+            black_box(recipes);
+        });
+    });
+}
+
+criterion_group!(benches, permute_ingredient, permute_ingredients, enumerate);
 criterion_main!(benches);
@@ -1,12 +1,13 @@
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use itertools::Itertools;
 
 use potionforge::{
     models::{
         traits::{GetByKey, GetName},
-        IngredientKey, INGREDIENTS, POTION_KINDS,
+        IngredientKey, Process, INGREDIENTS, POTION_KINDS,
     },
-    recommend::{AlchemistAttributes, BrandingCounts, MarketConditions},
-    simulate::SimulateConfig,
+    recommend::{AlchemistRoster, BrandingCounts, MarketConditions},
+    simulate::{AnalysisMemo, PartCounts, SimulateConfig},
     testdata::INGREDIENT_COMBINATIONS,
 };
 
@@ -33,9 +34,10 @@ pub fn simulate(c: &mut Criterion) {
         INGREDIENTS.get_by_key(&IngredientKey::Wizards).clone(),
     ];
     let config = SimulateConfig {
-        alchemists_attributes: AlchemistAttributes::new(),
+        alchemists: AlchemistRoster::new(),
         market_conditions: MarketConditions::new(),
         branding_counts: BrandingCounts::new(),
+        price_model: None,
     };
 
     let ingredients = combination.as_slice();
@@ -48,5 +50,52 @@ pub fn simulate(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, simulate, collect_parts);
+pub fn analyse_combinations(c: &mut Criterion) {
+    let raw_ingredients: Vec<_> = INGREDIENTS
+        .0
+        .iter()
+        .map(|(_, ingredient)| ingredient)
+        .collect();
+    let all_ingredients = ::potionforge::enumerate::permute_ingredients(
+        &::potionforge::process::PROCESS_VARIANTS,
+        raw_ingredients.as_slice(),
+        &[Process::Crush, Process::Dry],
+    );
+    let combinations: Vec<Vec<_>> = all_ingredients.iter().cloned().combinations(2).collect();
+
+    let mut group = c.benchmark_group("analyse_combinations");
+    group.sample_size(10);
+    group.bench_function("analyse", |b| {
+        b.iter(|| {
+            for combination in combinations.iter() {
+                black_box(::potionforge::simulate::analyse(combination));
+            }
+        });
+    });
+    let counted: Vec<_> = combinations
+        .iter()
+        .map(|combination| (combination, PartCounts::of_all(combination)))
+        .collect();
+    group.bench_function("analyse_counts", |b| {
+        b.iter(|| {
+            for (combination, counts) in counted.iter() {
+                black_box(::potionforge::simulate::analyse_counts(combination, counts));
+            }
+        });
+    });
+    group.bench_function("analyse_memoised", |b| {
+        b.iter(|| {
+            let memo = AnalysisMemo::new();
+            for (combination, counts) in counted.iter() {
+                black_box(::potionforge::simulate::analyse_memoised(
+                    combination,
+                    counts,
+                    &memo,
+                ));
+            }
+        });
+    });
+}
+
+criterion_group!(benches, simulate, collect_parts, analyse_combinations);
 criterion_main!(benches);
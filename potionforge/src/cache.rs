@@ -0,0 +1,203 @@
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    enumerate::EnumerateConfig,
+    models::{RecipeIntrinsics, INGREDIENTS, POTION_KINDS},
+};
+
+/// Version of the simulation rules, which the cache key cannot detect. Bump
+/// it whenever the analysis of a recipe changes so stale cache entries are
+/// ignored. Changes to the catalogue or potion kinds are picked up by
+/// `DATA_FINGERPRINT` instead, and the process rules are part of each key.
+pub const GAME_DATA_VERSION: u32 = 1;
+
+/// Version of the on-disk format of cached recipes. Bump it whenever
+/// `RecipeIntrinsics`, or a type it holds, gains, loses or changes a field.
+pub const CACHE_FORMAT_VERSION: u32 = 2;
+
+lazy_static::lazy_static! {
+    /// Hash of the catalogue and potion kinds.
+    static ref DATA_FINGERPRINT: u64 = {
+        let description = format!("{:?}|{:?}", INGREDIENTS.0, POTION_KINDS);
+        fnv1a(description.as_bytes())
+    };
+}
+
+/// Enumerated recipe intrinsics stored on disk, addressed by a hash of the
+/// configuration that produced them. Since intrinsics do not depend on the
+/// market, a cached set can be scored again after any market change.
+#[derive(Debug, Clone)]
+pub struct RecipeCache {
+    directory: PathBuf,
+}
+
+impl RecipeCache {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        RecipeCache {
+            directory: directory.into(),
+        }
+    }
+
+    /// Key identifying the recipes produced by the given configuration.
+    ///
+    /// List ordering does not affect the key, and settings only used when
+    /// scoring or recommending (such as utilisation or a minimum appeal) are
+    /// not part of it.
+    pub fn key(enumerate_config: &EnumerateConfig) -> String {
+        let mut ingredients = enumerate_config.ingredients.clone();
+        ingredients.sort();
+        let mut processes = enumerate_config.processes.clone();
+        processes.sort();
+        let mut filters: Vec<_> = enumerate_config
+            .filters
+            .iter()
+            .filter(|filter| filter.applies_to_intrinsics())
+            .collect();
+        filters.sort();
+        filters.dedup();
+
+        let description = format!(
+            "{}|{}|{:016x}|{}|{:?}|{:?}|{:?}|{}|{}|{:?}",
+            GAME_DATA_VERSION,
+            CACHE_FORMAT_VERSION,
+            *DATA_FINGERPRINT,
+            env!("CARGO_PKG_VERSION"),
+            enumerate_config.variants.rules(),
+            ingredients,
+            processes,
+            enumerate_config.arcane_power,
+            enumerate_config.repeated_ingredients,
+            filters,
+        );
+
+        format!("{:016x}", fnv1a(description.as_bytes()))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.directory.join(format!("{}.bin", key))
+    }
+
+    /// Load the recipes stored under key, if present and readable.
+    pub fn load(&self, key: &str) -> Option<Vec<RecipeIntrinsics>> {
+        let file = File::open(self.path(key)).ok()?;
+        bincode::deserialize_from(BufReader::new(file)).ok()
+    }
+
+    /// Store the recipes under key. They are written to a temporary file
+    /// and renamed into place, so an interrupted write never leaves a
+    /// truncated entry behind.
+    pub fn store(&self, key: &str, recipes: &[RecipeIntrinsics]) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(&self.directory)?;
+        let temporary = self
+            .directory
+            .join(format!("{}.bin.{}.tmp", key, std::process::id()));
+        let written = write_recipes(&temporary, recipes)
+            .and_then(|()| fs::rename(&temporary, self.path(key)).map_err(Into::into));
+        if written.is_err() {
+            // Best effort: the write error is the one worth reporting.
+            let _ = fs::remove_file(&temporary);
+        }
+        written
+    }
+}
+
+fn write_recipes(path: &Path, recipes: &[RecipeIntrinsics]) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    bincode::serialize_into(&mut writer, recipes)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// 64-bit FNV-1a, used because it is stable across Rust releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        models::{traits::GetByKey, IngredientKey, Process, INGREDIENTS},
+        process::{VariantTable, PROCESS_RULES, PROCESS_VARIANTS},
+        simulate::analyse,
+        testdata::{enumerate_config, unique_temp_dir},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_key_ignores_ordering() {
+        let a = enumerate_config(
+            &[IngredientKey::Sage, IngredientKey::Catnip],
+            3,
+            vec![Process::Dry, Process::Crush],
+        );
+        let b = EnumerateConfig {
+            utilisation: 5,
+            ..enumerate_config(
+                &[IngredientKey::Catnip, IngredientKey::Sage],
+                3,
+                vec![Process::Crush, Process::Dry],
+            )
+        };
+        let c = enumerate_config(
+            &[IngredientKey::Catnip, IngredientKey::Sage],
+            4,
+            vec![Process::Crush, Process::Dry],
+        );
+
+        assert_eq!(RecipeCache::key(&a), RecipeCache::key(&b));
+        assert_ne!(RecipeCache::key(&a), RecipeCache::key(&c));
+    }
+
+    #[test]
+    fn test_key_depends_on_rules() {
+        let mut rules = PROCESS_RULES.clone();
+        rules.ferment.clear();
+        let config = |variants| EnumerateConfig {
+            variants,
+            ..enumerate_config(
+                &[IngredientKey::Sage, IngredientKey::Catnip],
+                3,
+                vec![Process::Ferment],
+            )
+        };
+
+        assert_ne!(
+            RecipeCache::key(&config(PROCESS_VARIANTS.clone())),
+            RecipeCache::key(&config(Arc::new(VariantTable::from_rules(&rules))))
+        );
+    }
+
+    #[test]
+    fn test_store_and_load() {
+        let ingredients = vec![
+            INGREDIENTS.get_by_key(&IngredientKey::Flyagaric).clone(),
+            INGREDIENTS.get_by_key(&IngredientKey::Lupine).clone(),
+        ];
+        let recipes = vec![analyse(&ingredients).unwrap()];
+
+        let directory = unique_temp_dir("potionforge-cache-test");
+        let cache = RecipeCache::new(&directory);
+        cache.store("roundtrip", &recipes).unwrap();
+
+        assert_eq!(cache.load("roundtrip"), Some(recipes));
+        assert_eq!(cache.load("missing"), None);
+        // Only the entry is left behind, not the temporary file.
+        let entries: Vec<_> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, vec!["roundtrip.bin"]);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
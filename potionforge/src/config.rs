@@ -0,0 +1,845 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::de::{self, Deserializer, Unexpected, Visitor};
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+
+use crate::enumerate::{default_filters, CandidateFilter, EnumerateConfig};
+use crate::forge::{check_arcane_power, check_count, check_utilisation, ConfigError, Forge};
+use crate::models::{BrandingCategory, IngredientKey, PotionKindKey, Process};
+use crate::plan::{PlanConfig, PlanDay};
+use crate::process::{ProcessRules, VariantTable, PROCESS_VARIANTS};
+use crate::recommend::{
+    roster_from_attributes, AlchemistAttributes, AlchemistRoster, BrandingCounts, IngredientCounts,
+    MarketConditions, RecommendConfig,
+};
+use crate::scenario::{MarketScenario, ScenarioObjective};
+use crate::simulate::{PriceModel, SimulateConfig};
+
+/// Configuration file written by `potionkeeper init`, documenting every key.
+pub const EXAMPLE_CONFIG: &str = include_str!("recommend.yml.example");
+
+/// Files merged in order beneath the file listing them.
+const INCLUDE_KEY: &str = "include";
+/// Named sets of settings, one of which can be merged over the file.
+const PROFILES_KEY: &str = "profiles";
+
+/// Contents of a recommend configuration file, shared by every command.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecommendFile {
+    #[serde(deserialize_with = "arcane_power")]
+    pub arcane_power: i64,
+    #[serde(deserialize_with = "utilisation")]
+    pub utilisation: i32,
+    pub processes: Vec<Process>,
+    #[serde(deserialize_with = "counts")]
+    pub ingredients: IngredientCounts,
+    pub alchemists: AlchemistsConfig,
+    pub market: MarketConditions,
+    #[serde(deserialize_with = "counts")]
+    pub branding: BrandingCounts,
+    pub potions: Vec<PotionKindKey>,
+    /// Allow a recipe to use the same ingredient more than once.
+    #[serde(default)]
+    pub repeated_ingredients: bool,
+    #[serde(default = "default_filters")]
+    pub filters: Vec<CandidateFilter>,
+    #[serde(default)]
+    pub prices: Option<PriceModel>,
+    #[serde(default)]
+    pub days: Vec<PlanDayConfig>,
+    #[serde(default)]
+    pub scenarios: Vec<MarketScenario>,
+    #[serde(default)]
+    pub scenario_objective: ScenarioObjective,
+    /// Process rules of a newer game release, replacing the built in ones.
+    #[serde(default)]
+    pub process_rules: Option<ProcessRules>,
+}
+
+/// A day of the planning horizon. Days without a market use the top level one.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlanDayConfig {
+    #[serde(default)]
+    pub market: Option<MarketConditions>,
+    #[serde(default, deserialize_with = "counts")]
+    pub restock: IngredientCounts,
+}
+
+/// One file of a layered configuration, validated on its own so errors point
+/// at the file and line they were written on. Every setting may come from
+/// another layer, and those merged key by key are validated after merging.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(dead_code)]
+struct ConfigSource {
+    include: Option<Vec<String>>,
+    profiles: Option<HashMap<String, ConfigSource>>,
+    arcane_power: Option<ArcanePower>,
+    utilisation: Option<Utilisation>,
+    processes: Option<Vec<Process>>,
+    ingredients: Option<HashMap<IngredientKey, Count>>,
+    alchemists: Option<AlchemistsConfig>,
+    market: Option<MarketConditions>,
+    branding: Option<HashMap<BrandingCategory, Count>>,
+    potions: Option<Vec<PotionKindKey>>,
+    repeated_ingredients: Option<bool>,
+    filters: Option<Vec<CandidateFilter>>,
+    prices: Option<Value>,
+    days: Option<Vec<PlanDayConfig>>,
+    scenarios: Option<Vec<MarketScenario>>,
+    scenario_objective: Option<ScenarioObjective>,
+    process_rules: Option<Value>,
+}
+
+/// Alchemists as a roster, or as attribute counts applied to every
+/// department like older configuration files.
+#[derive(Debug, Clone)]
+pub enum AlchemistsConfig {
+    Roster(AlchemistRoster),
+    Counts(AlchemistAttributes),
+}
+
+impl AlchemistsConfig {
+    pub fn into_roster(self) -> AlchemistRoster {
+        match self {
+            AlchemistsConfig::Roster(roster) => roster,
+            AlchemistsConfig::Counts(counts) => roster_from_attributes(&counts),
+        }
+    }
+}
+
+/// A sequence is a roster and a mapping is counts, so errors inside either
+/// keep their position instead of failing every variant at once.
+impl<'de> Deserialize<'de> for AlchemistsConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AlchemistsVisitor;
+
+        impl<'de> Visitor<'de> for AlchemistsVisitor {
+            type Value = AlchemistsConfig;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a list of alchemists or a mapping of attribute counts")
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+                AlchemistRoster::deserialize(de::value::SeqAccessDeserializer::new(seq))
+                    .map(AlchemistsConfig::Roster)
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                counts(de::value::MapAccessDeserializer::new(map)).map(AlchemistsConfig::Counts)
+            }
+        }
+
+        deserializer.deserialize_any(AlchemistsVisitor)
+    }
+}
+
+impl RecommendFile {
+    /// Parse and validate a configuration file.
+    pub fn from_yaml(yaml: &str) -> Result<Self, InvalidConfig> {
+        let file: Self = serde_yaml::from_str(yaml).map_err(InvalidConfig::from)?;
+        file.validate()?;
+        Ok(file)
+    }
+
+    /// Check the whole configuration as `Forge` does. Single values were
+    /// checked where they appear in the file, so what is left are the
+    /// settings that depend on one another, which have no single position.
+    fn validate(&self) -> Result<(), InvalidConfig> {
+        self.forge().build()?;
+        Ok(())
+    }
+
+    /// The pipeline builder with every setting of the file.
+    pub fn forge(&self) -> Forge {
+        let mut forge = Forge::new()
+            .ingredients(self.ingredients.clone())
+            .processes(self.processes.clone())
+            .arcane_power(self.arcane_power)
+            .utilisation(self.utilisation)
+            .repeated_ingredients(self.repeated_ingredients)
+            .filters(self.filters.clone())
+            .alchemists(self.alchemists.clone().into_roster())
+            .market(self.market.clone())
+            .branding(self.branding.clone())
+            .potions(self.potions.clone())
+            .scenarios(self.scenarios.clone(), self.scenario_objective);
+        if let Some(prices) = &self.prices {
+            forge = forge.prices(prices.clone());
+        }
+        if let Some(rules) = &self.process_rules {
+            forge = forge.process_rules(rules.clone());
+        }
+        forge
+    }
+
+    /// Read, parse and validate the configuration file at `path`, with its
+    /// includes.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Self::load_layered(path, &ConfigLayers::default())
+    }
+
+    /// Read the configuration file at `path`, merge the layers over it and
+    /// validate the result. Each file is validated on its own first, so
+    /// errors in it point at its lines. Errors found after merging name the
+    /// override that set the value, if any, and have no position.
+    pub fn load_layered<P: AsRef<Path>>(
+        path: P,
+        layers: &ConfigLayers,
+    ) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        let merged = merge_layers(path, layers)?;
+
+        // Errors point into the file itself unless anything was merged.
+        if serde_yaml::from_str::<Value>(&contents).ok() == Some(merged.clone()) {
+            return Ok(Self::from_yaml(&contents)?);
+        }
+        Self::from_yaml(&serde_yaml::to_string(&merged)?).map_err(|error| {
+            // Positions in the merged configuration are not in any file.
+            let error = InvalidConfig {
+                line: None,
+                column: None,
+                ..error
+            };
+            match overriding(&layers.overrides, &error.message) {
+                Some(assignment) => format!("override `{}`: {}", assignment, error).into(),
+                None => format!("{} (in the merged configuration)", error).into(),
+            }
+        })
+    }
+
+    /// Enumerate recipes of the available ingredients.
+    pub fn enumerate_config(&self) -> EnumerateConfig {
+        EnumerateConfig {
+            ingredients: self.ingredients.keys().cloned().collect(),
+            arcane_power: self.arcane_power,
+            utilisation: self.utilisation,
+            processes: self.processes.clone(),
+            repeated_ingredients: self.repeated_ingredients,
+            filters: self.filters.clone(),
+            variants: self.variants(),
+        }
+    }
+
+    /// Processed variants of the catalogue under the configured rules.
+    pub fn variants(&self) -> Arc<VariantTable> {
+        match &self.process_rules {
+            Some(rules) => Arc::new(VariantTable::from_rules(rules)),
+            None => PROCESS_VARIANTS.clone(),
+        }
+    }
+
+    pub fn simulate_config(&self) -> SimulateConfig {
+        SimulateConfig {
+            alchemists: self.alchemists.clone().into_roster(),
+            market_conditions: self.market.clone(),
+            branding_counts: self.branding.clone(),
+            price_model: self.prices.clone(),
+        }
+    }
+
+    pub fn recommend_config(&self) -> RecommendConfig {
+        RecommendConfig {
+            available_ingredients: self.ingredients.clone(),
+            utilisation: self.utilisation,
+            potions: self.potions.clone(),
+        }
+    }
+
+    pub fn plan_config(&self) -> PlanConfig {
+        PlanConfig {
+            available_ingredients: self.ingredients.clone(),
+            utilisation: self.utilisation,
+            days: self
+                .days
+                .iter()
+                .map(|day| PlanDay {
+                    market_conditions: day.market.clone().unwrap_or_else(|| self.market.clone()),
+                    restock: day.restock.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Settings merged over a configuration file and its includes.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigLayers {
+    /// Profile of the `profiles` section to merge over the file.
+    pub profile: Option<String>,
+    /// Overrides such as `market.Speed=[HighDemand]`, applied last in order.
+    pub overrides: Vec<String>,
+}
+
+/// The effective configuration of the file at `path`: its includes merged in
+/// order beneath it, then the selected profile, then the overrides. Mappings
+/// are merged key by key, anything else is replaced.
+pub fn merge_layers<P: AsRef<Path>>(
+    path: P,
+    layers: &ConfigLayers,
+) -> Result<Value, Box<dyn Error>> {
+    let mut merged = read_with_includes(path.as_ref(), &mut Vec::new())?;
+
+    let profiles = match &mut merged {
+        Value::Mapping(mapping) => mapping.remove(&Value::from(PROFILES_KEY)),
+        _ => None,
+    };
+    if let Some(name) = &layers.profile {
+        let mut profiles = match profiles {
+            Some(Value::Mapping(profiles)) => profiles,
+            None | Some(Value::Null) => Mapping::new(),
+            Some(_) => return Err("profiles: expected a mapping of names to settings".into()),
+        };
+        match profiles.remove(&Value::from(name.as_str())) {
+            Some(profile) => merge(&mut merged, profile),
+            None => {
+                let names: Vec<&str> = profiles
+                    .iter()
+                    .filter_map(|(name, _)| name.as_str())
+                    .collect();
+                let mut message = format!("unknown profile `{}`", name);
+                if let Some(suggestion) = closest(name, names.iter().copied()) {
+                    message += &format!(" (did you mean `{}`?)", suggestion);
+                }
+                return Err(message.into());
+            }
+        }
+    }
+
+    for assignment in &layers.overrides {
+        apply_override(&mut merged, assignment)?;
+    }
+    Ok(merged)
+}
+
+/// Read a configuration file with the files it includes merged beneath it.
+/// `stack` holds the files being read, to reject includes of themselves.
+fn read_with_includes(path: &Path, stack: &mut Vec<PathBuf>) -> Result<Value, Box<dyn Error>> {
+    let in_file = |error: &dyn fmt::Display| format!("{}: {}", path.display(), error);
+    let canonical = fs::canonicalize(path).map_err(|error| in_file(&error))?;
+    if stack.contains(&canonical) {
+        return Err(in_file(&"included by itself").into());
+    }
+
+    let contents = fs::read_to_string(path).map_err(|error| in_file(&error))?;
+    let mut value: Value =
+        serde_yaml::from_str(&contents).map_err(|error| in_file(&InvalidConfig::from(error)))?;
+    if value.is_mapping() {
+        serde_yaml::from_str::<ConfigSource>(&contents)
+            .map_err(|error| in_file(&InvalidConfig::from(error)))?;
+    }
+    let includes = match &mut value {
+        Value::Mapping(mapping) => mapping.remove(&Value::from(INCLUDE_KEY)),
+        Value::Null => {
+            value = Value::Mapping(Mapping::new());
+            None
+        }
+        _ => return Err(in_file(&"expected a mapping of settings").into()),
+    };
+    let includes: Vec<String> = match includes {
+        Some(includes) => serde_yaml::from_value(includes)
+            .map_err(|error| in_file(&format!("{}: {}", INCLUDE_KEY, error)))?,
+        None => Vec::new(),
+    };
+
+    stack.push(canonical);
+    let mut merged = Value::Mapping(Mapping::new());
+    for include in includes {
+        let include = path.parent().unwrap_or_else(|| Path::new("")).join(include);
+        merge(&mut merged, read_with_includes(&include, stack)?);
+    }
+    stack.pop();
+
+    merge(&mut merged, value);
+    Ok(merged)
+}
+
+fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Mapping(base), Value::Mapping(layer)) => {
+            for (key, value) in layer {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+/// Set the value at a dotted key path from a `key.path=value` override, the
+/// value being YAML.
+fn apply_override(config: &mut Value, assignment: &str) -> Result<(), Box<dyn Error>> {
+    let (path, value) = assignment
+        .split_once('=')
+        .ok_or_else(|| format!("override `{}` is not of the form key=value", assignment))?;
+    let value: Value = serde_yaml::from_str(value)
+        .map_err(|error| format!("override `{}`: {}", assignment, error))?;
+
+    let mut target = config;
+    for key in path.trim().split('.') {
+        if target.is_null() {
+            *target = Value::Mapping(Mapping::new());
+        }
+        let mapping = match target {
+            Value::Mapping(mapping) => mapping,
+            _ => {
+                return Err(format!("override `{}`: `{}` is not a mapping", assignment, key).into())
+            }
+        };
+        let key = Value::from(key);
+        if !mapping.contains_key(&key) {
+            mapping.insert(key.clone(), Value::Null);
+        }
+        target = mapping.get_mut(&key).unwrap();
+    }
+    *target = value;
+    Ok(())
+}
+
+/// The last override setting the value an error message is about, from the
+/// key path the message starts with.
+fn overriding<'a>(overrides: &'a [String], message: &str) -> Option<&'a str> {
+    overrides
+        .iter()
+        .rev()
+        .find(|assignment| {
+            let path = match assignment.split_once('=') {
+                Some((path, _)) => path.trim(),
+                None => return false,
+            };
+            message
+                .strip_prefix(path)
+                .is_some_and(|rest| rest.starts_with([':', '.', '[']))
+        })
+        .map(String::as_str)
+}
+
+/// Accepts integers passing one of the checks `Forge` makes, rejecting others
+/// where they appear in the file.
+struct Checked<T>(fn(T) -> Result<T, ConfigError>);
+
+impl<'de, T: TryFrom<i64>> Visitor<'de> for Checked<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a {} bit integer", std::mem::size_of::<T>() * 8)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<T, E> {
+        let converted =
+            T::try_from(value).map_err(|_| E::invalid_value(Unexpected::Signed(value), &self))?;
+        (self.0)(converted).map_err(E::custom)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<T, E> {
+        match i64::try_from(value) {
+            Ok(value) => self.visit_i64(value),
+            Err(_) => Err(E::invalid_value(Unexpected::Unsigned(value), &self)),
+        }
+    }
+}
+
+fn arcane_power<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    deserializer.deserialize_i64(Checked(check_arcane_power))
+}
+
+fn utilisation<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+    deserializer.deserialize_i64(Checked(check_utilisation))
+}
+
+/// A valid arcane power, checked without keeping it.
+struct ArcanePower;
+
+impl<'de> Deserialize<'de> for ArcanePower {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        arcane_power(deserializer).map(|_| ArcanePower)
+    }
+}
+
+/// A valid utilisation, checked without keeping it.
+struct Utilisation;
+
+impl<'de> Deserialize<'de> for Utilisation {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        utilisation(deserializer).map(|_| Utilisation)
+    }
+}
+
+/// A count of ingredients or branding, which cannot be negative.
+struct Count(i32);
+
+impl<'de> Deserialize<'de> for Count {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer
+            .deserialize_i64(Checked(check_count))
+            .map(Count)
+    }
+}
+
+fn counts<'de, D, K>(deserializer: D) -> Result<HashMap<K, i32>, D::Error>
+where
+    D: Deserializer<'de>,
+    K: Deserialize<'de> + Eq + Hash,
+{
+    let counts: HashMap<K, Count> = HashMap::deserialize(deserializer)?;
+    Ok(counts
+        .into_iter()
+        .map(|(key, Count(count))| (key, count))
+        .collect())
+}
+
+/// A configuration file that does not match the schema, with the position of
+/// the offending value when known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidConfig {
+    pub message: String,
+    /// The closest valid name to a misspelled key, ingredient or other name.
+    pub suggestion: Option<String>,
+    /// 1-based line of the offending value.
+    pub line: Option<usize>,
+    /// 1-based column of the offending value.
+    pub column: Option<usize>,
+}
+
+impl From<serde_yaml::Error> for InvalidConfig {
+    fn from(error: serde_yaml::Error) -> Self {
+        let mut message = error.to_string();
+        let location = error.location();
+        if let Some(location) = &location {
+            let suffix = format!(" at line {} column {}", location.line(), location.column());
+            if message.ends_with(&suffix) {
+                message.truncate(message.len() - suffix.len());
+            }
+        }
+        InvalidConfig {
+            suggestion: suggest(&message),
+            message,
+            line: location.as_ref().map(|location| location.line()),
+            column: location.as_ref().map(|location| location.column()),
+        }
+    }
+}
+
+impl From<ConfigError> for InvalidConfig {
+    fn from(error: ConfigError) -> Self {
+        // Name the setting at fault the way serde does, when there is one.
+        let key = match &error {
+            ConfigError::NoIngredients => Some("ingredients"),
+            ConfigError::NegativeCount(_) => None,
+            ConfigError::ArcanePower(_) => Some("arcane_power"),
+            ConfigError::Utilisation(_) => Some("utilisation"),
+            ConfigError::TooManyPotions(..) => Some("potions"),
+            ConfigError::BulkWithoutPrices => Some("branding"),
+            ConfigError::Scenarios(_) => Some("scenarios"),
+        };
+        InvalidConfig {
+            message: match key {
+                Some(key) => format!("{}: {}", key, error),
+                None => error.to_string(),
+            },
+            suggestion: None,
+            line: None,
+            column: None,
+        }
+    }
+}
+
+impl fmt::Display for InvalidConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, "line {} column {}: ", line, column)?;
+        }
+        write!(f, "{}", self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " (did you mean `{}`?)", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for InvalidConfig {}
+
+/// Suggest the closest expected name for an unknown variant or field, from
+/// the names serde lists in the message.
+fn suggest(message: &str) -> Option<String> {
+    let start = ["unknown variant `", "unknown field `"]
+        .iter()
+        .find_map(|prefix| message.find(prefix).map(|index| index + prefix.len()))?;
+    let rest = &message[start..];
+    let end = rest.find('`')?;
+    let unknown = &rest[..end];
+    let expected = &rest[rest.find("expected")?..];
+
+    closest(unknown, expected.split('`').skip(1).step_by(2)).map(str::to_string)
+}
+
+/// The name closest to a misspelled one, if any is close enough.
+fn closest<'a>(unknown: &str, names: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    names
+        .map(|name| {
+            (
+                edit_distance(&unknown.to_lowercase(), &name.to_lowercase()),
+                name,
+            )
+        })
+        .filter(|(distance, _)| *distance <= (unknown.chars().count() / 3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
+}
+
+/// Levenshtein distance between two names.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        models::{AlchemistAttribute, Department, MarketCondition},
+        testdata::unique_temp_dir,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_alchemists_config() {
+        let roster: AlchemistsConfig = serde_yaml::from_str(
+            "- name: Rosa\n  attributes: [Herbalist]\n  departments: [Health]\n",
+        )
+        .unwrap();
+        let roster = roster.into_roster();
+        assert_eq!(roster.len(), 1);
+        assert_eq!(roster[0].departments, vec![Department::Health]);
+
+        let counts: AlchemistsConfig =
+            serde_yaml::from_str("Herbalist: 2\nEnchanter: 0\n").unwrap();
+        let roster = counts.into_roster();
+        assert_eq!(roster.len(), 2);
+        assert!(roster.iter().all(|alchemist| alchemist.attributes
+            == vec![AlchemistAttribute::Herbalist]
+            && alchemist.departments.len() == 3));
+
+        let error = serde_yaml::from_str::<AlchemistsConfig>(
+            "- name: Rosa\n  attributes: [Herbalist]\n  departments: [Health]\n  extra: 1\n",
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("unknown field `extra`"));
+
+        let error = InvalidConfig::from(
+            serde_yaml::from_str::<AlchemistsConfig>(
+                "- name: Rosa\n  attributes: [Herbalst]\n  departments: [Health]\n",
+            )
+            .unwrap_err(),
+        );
+        assert_eq!(error.suggestion.as_deref(), Some("Herbalist"));
+        assert_eq!(error.line, Some(2));
+        assert_eq!(error.column, Some(16));
+
+        let example = RecommendFile::from_yaml(EXAMPLE_CONFIG).unwrap();
+        assert!(example.alchemists.into_roster().is_empty());
+        assert_eq!(example.ingredients[&IngredientKey::Sage], 1);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let invalid = |from: &str, to: &str| {
+            assert!(EXAMPLE_CONFIG.contains(from));
+            RecommendFile::from_yaml(&EXAMPLE_CONFIG.replacen(from, to, 1)).unwrap_err()
+        };
+        let line_of = |text: &str| {
+            EXAMPLE_CONFIG
+                .lines()
+                .position(|line| line.starts_with(text))
+                .unwrap()
+                + 1
+        };
+
+        let error = invalid("  Sage: 1", "  sagee: 1");
+        assert_eq!(error.suggestion.as_deref(), Some("Sage"));
+        assert_eq!(error.line, Some(line_of("  Sage: 1")));
+        assert_eq!(error.column, Some(3));
+        assert!(error
+            .message
+            .starts_with("ingredients: unknown variant `sagee`"));
+
+        let error = invalid("  Thyme: 1", "  Thyme: -2");
+        assert_eq!(error.line, Some(line_of("  Thyme: 1")));
+        assert_eq!(error.column, Some(10));
+        assert!(error.message.contains("count -2 is negative"));
+
+        let error = invalid("utilisation: 3", "utilization: 3");
+        assert_eq!(error.suggestion.as_deref(), Some("utilisation"));
+        assert_eq!(error.line, Some(line_of("utilisation")));
+
+        let error = invalid("arcane_power: 2", "arcane_power: 9");
+        assert_eq!(error.line, Some(1));
+        assert_eq!(error.column, Some(15));
+        assert_eq!(
+            error.to_string(),
+            "line 1 column 15: arcane_power: arcane power 9 is outside 2 to 5"
+        );
+
+        let error = invalid("  Bulk: 0", "  Bulk: 1");
+        assert_eq!(error.line, None);
+        assert_eq!(
+            error.to_string(),
+            "branding: Bulk branding needs prices to weigh bulk pricing"
+        );
+    }
+
+    #[test]
+    fn test_config_layers() {
+        let directory = unique_temp_dir("potionforge-config-test");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("base.yml"), EXAMPLE_CONFIG).unwrap();
+        fs::write(
+            directory.join("team.yml"),
+            "include: [base.yml]\nmarket:\n  Speed: [InDemand]\nprofiles:\n  weekend:\n    arcane_power: 3\n",
+        )
+        .unwrap();
+        fs::write(directory.join("loop.yml"), "include: [loop.yml]\n").unwrap();
+        fs::write(
+            directory.join("partial.yml"),
+            "arcane_power: 2\nprofiles:\n  weekend:\n    arcane_power: 3\n",
+        )
+        .unwrap();
+        fs::write(
+            directory.join("typo.yml"),
+            "include: [base.yml]\nprofiles:\n  weekend:\n    ingredients:\n      Sagee: 1\n",
+        )
+        .unwrap();
+        let team = directory.join("team.yml");
+        let speed = |config: &RecommendFile| config.market[&PotionKindKey::Speed].clone();
+
+        let config = RecommendFile::load(&team).unwrap();
+        assert_eq!(config.arcane_power, 2);
+        assert_eq!(speed(&config), vec![MarketCondition::InDemand]);
+        assert_eq!(
+            config.market[&PotionKindKey::Mana],
+            vec![MarketCondition::HighDemand]
+        );
+
+        let weekend = ConfigLayers {
+            profile: Some("weekend".to_string()),
+            overrides: Vec::new(),
+        };
+        assert_eq!(
+            RecommendFile::load_layered(&team, &weekend)
+                .unwrap()
+                .arcane_power,
+            3
+        );
+
+        let overridden = ConfigLayers {
+            profile: Some("weekend".to_string()),
+            overrides: vec![
+                "arcane_power=4".to_string(),
+                "market.Speed=[HighDemand]".to_string(),
+            ],
+        };
+        let config = RecommendFile::load_layered(&team, &overridden).unwrap();
+        assert_eq!(config.arcane_power, 4);
+        assert_eq!(speed(&config), vec![MarketCondition::HighDemand]);
+
+        let misspelled = ConfigLayers {
+            profile: Some("weekedn".to_string()),
+            overrides: Vec::new(),
+        };
+        let error = RecommendFile::load_layered(&team, &misspelled).unwrap_err();
+        assert!(error.to_string().contains("did you mean `weekend`?"));
+
+        let invalid = ConfigLayers {
+            profile: None,
+            overrides: vec!["utilisation=0".to_string()],
+        };
+        let error = RecommendFile::load_layered(&team, &invalid).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "override `utilisation=0`: utilisation: utilisation 0 is below 1"
+        );
+
+        let misspelled = ConfigLayers {
+            profile: None,
+            overrides: vec!["market.Speed=[HighDemnd]".to_string()],
+        };
+        let error = RecommendFile::load_layered(&team, &misspelled)
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("override `market.Speed=[HighDemnd]`: market.Speed[0]:"));
+        assert!(error.ends_with("(did you mean `HighDemand`?)"));
+
+        // Settings missing once merged are not in any file or override.
+        let error = RecommendFile::load_layered(directory.join("partial.yml"), &weekend)
+            .unwrap_err()
+            .to_string();
+        assert!(error.starts_with("missing field"));
+        assert!(error.ends_with("(in the merged configuration)"));
+
+        // Errors in a file with includes or profiles point into that file.
+        let error = RecommendFile::load(directory.join("typo.yml"))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("typo.yml: line 5 column 7"));
+        assert!(error.contains("did you mean `Sage`?"));
+
+        let error = RecommendFile::load(directory.join("loop.yml")).unwrap_err();
+        assert!(error.to_string().ends_with("included by itself"));
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_process_rules_config() {
+        let example = RecommendFile::from_yaml(EXAMPLE_CONFIG).unwrap();
+        assert!(Arc::ptr_eq(&example.variants(), &PROCESS_VARIANTS));
+
+        // Rules of a newer release kept in a file of their own.
+        let rules = "cuts:\n  - process: Crush\n    kept: [3, 0]\nferment: []\ninfuse: []\n";
+        let directory = unique_temp_dir("potionforge-rules-test");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("base.yml"), EXAMPLE_CONFIG).unwrap();
+        fs::write(
+            directory.join("rules.yml"),
+            format!("process_rules:\n  {}", rules.replace("\n", "\n  ")),
+        )
+        .unwrap();
+        fs::write(
+            directory.join("patched.yml"),
+            "include: [base.yml, rules.yml]\n",
+        )
+        .unwrap();
+
+        let config = RecommendFile::load(directory.join("patched.yml")).unwrap();
+        assert_eq!(
+            config.enumerate_config().variants.rules(),
+            &ProcessRules::from_yaml(rules).unwrap()
+        );
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,77 +1,487 @@
+use std::collections::HashMap;
+
 use itertools::Itertools;
-use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use rayon::prelude::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 
 use crate::{
-    enumerate::{permute_ingredients, EnumerateConfig},
+    enumerate::{
+        collapse_equivalent_ingredients, permute_ingredients, CandidateFilter, EnumerateConfig,
+    },
     models::{
-        traits::GetByKey, Ingredient, IngredientPart, OverallToxicity, Recipe, ToxicityEffect,
-        INGREDIENTS, POTION_KINDS,
+        traits::GetByKey, Ingredient, IngredientKey, IngredientPart, IngredientProcess,
+        OverallToxicity, PotionKindKey, Recipe, RecipeIntrinsics, ToxicityEffect, INGREDIENTS,
+        POTION_KINDS,
+    },
+    progress::{CancellationToken, Cancelled, ProgressSink, Stage},
+    recommend::{
+        is_priced_bulk, maximise_appeal, maximise_potency, maximise_recipes, RecommendConfig,
     },
-    recommend::{maximise_appeal, maximise_potency, maximise_recipes, RecommendConfig},
-    simulate::{self, collect_parts, SimulateConfig},
+    simulate::{self, AnalysisMemo, PartCounts, SimulateConfig},
 };
 
 pub fn enumerate_and_simulate(
     enumerate_config: &EnumerateConfig,
     simulate_config: &SimulateConfig,
+    progress: &dyn ProgressSink,
+    cancel: &CancellationToken,
+) -> Result<Vec<Recipe>, Cancelled> {
+    let intrinsics = enumerate(enumerate_config, progress, cancel)?;
+    Ok(score_recipes(
+        &intrinsics,
+        simulate_config,
+        &enumerate_config.filters,
+    ))
+}
+
+/// Enumerate the market independent properties of every possible recipe.
+/// The result can be kept and scored again whenever the market changes.
+pub fn enumerate(
+    enumerate_config: &EnumerateConfig,
+    progress: &dyn ProgressSink,
+    cancel: &CancellationToken,
+) -> Result<Vec<RecipeIntrinsics>, Cancelled> {
+    enumerate_matching(enumerate_config, progress, cancel, &[])
+}
+
+/// Score recipes against the market, branding and alchemists, dropping any
+/// the filters reject.
+pub fn score_recipes(
+    intrinsics: &[RecipeIntrinsics],
+    simulate_config: &SimulateConfig,
+    filters: &[CandidateFilter],
 ) -> Vec<Recipe> {
+    intrinsics
+        .par_iter()
+        .flat_map_iter(|intrinsics| simulate::score_offers(intrinsics, simulate_config))
+        .filter(|recipe| is_recipe_reasonable(recipe, filters))
+        .collect()
+}
+
+/// Find every recipe for a single potion kind, best appeal first and then
+/// best potency. Only combinations containing both the main effect and the
+/// element of the potion kind are generated.
+pub fn enumerate_and_simulate_potion(
+    potion_kind_key: &PotionKindKey,
+    enumerate_config: &EnumerateConfig,
+    simulate_config: &SimulateConfig,
+    progress: &dyn ProgressSink,
+    cancel: &CancellationToken,
+) -> Result<Vec<Recipe>, Cancelled> {
+    let (main_effect, element) = POTION_KINDS.get_by_key(potion_kind_key).parts;
+
+    let required = [
+        IngredientPart::MainEffect(main_effect),
+        IngredientPart::Element(element),
+    ];
+    let mut intrinsics = enumerate_matching(enumerate_config, progress, cancel, &required)?;
+    intrinsics.retain(|intrinsics| intrinsics.potion_kind_key == *potion_kind_key);
+
+    let mut recipes = score_recipes(&intrinsics, simulate_config, &enumerate_config.filters);
+    recipes.sort_by(|a, b| {
+        b.overall_appeal
+            .cmp(&a.overall_appeal)
+            .then(b.overall_potency.cmp(&a.overall_potency))
+    });
+    Ok(recipes)
+}
+
+/// Combinations analysed between progress updates and cancellation checks.
+const ENUMERATE_BATCH_SIZE: usize = 10_000;
+
+/// Enumerate the combinations that have every required part in at least one
+/// of their ingredients. Combinations missing one are never generated.
+fn enumerate_matching(
+    enumerate_config: &EnumerateConfig,
+    progress: &dyn ProgressSink,
+    cancel: &CancellationToken,
+    required: &[IngredientPart],
+) -> Result<Vec<RecipeIntrinsics>, Cancelled> {
     let raw_ingredients: Vec<_> = enumerate_config
         .ingredients
         .iter()
         .map(|key| INGREDIENTS.get_by_key(key))
         .collect();
 
-    let all_ingredients =
-        permute_ingredients(raw_ingredients.as_slice(), &enumerate_config.processes);
+    let all_ingredients = permute_ingredients(
+        &enumerate_config.variants,
+        raw_ingredients.as_slice(),
+        &enumerate_config.processes,
+    );
+
+    // Only enumerate one variant of each ingredient per distinct set of parts.
+    let classes = collapse_equivalent_ingredients(&enumerate_config.variants, all_ingredients);
+    let representatives: Vec<Ingredient> = classes
+        .iter()
+        .map(|class| class.representative.clone())
+        .collect();
+    // Classes of more than one process chain, restored on the recipes found.
+    let alternatives: HashMap<Ingredient, Vec<IngredientProcess>> = classes
+        .into_iter()
+        .filter(|class| class.processes.len() > 1)
+        .map(|class| (class.representative, class.processes))
+        .collect();
+    let part_counts: Vec<PartCounts> = representatives.iter().map(PartCounts::of).collect();
+    // Which ingredients carry each required part.
+    let carriers: Vec<Vec<bool>> = required
+        .iter()
+        .map(|part| {
+            part_counts
+                .iter()
+                .map(|counts| counts.get(part) > 0)
+                .collect()
+        })
+        .collect();
+    let repeated_ingredients = enumerate_config.repeated_ingredients;
+    let count = |k: i64| {
+        count_covering(
+            representatives.len(),
+            &carriers,
+            k as u64,
+            repeated_ingredients,
+        )
+    };
+
+    let total = (2..=enumerate_config.arcane_power).map(count).sum();
+    let memo = cfg!(feature = "analysis-memo").then(AnalysisMemo::new);
+    progress.stage_started(Stage::Enumerate, Some(total));
 
-    (2..=enumerate_config.arcane_power)
+    let intrinsics = (2..=enumerate_config.arcane_power)
         .into_par_iter()
         .flat_map(|k| {
-            let combinations: Vec<Vec<Ingredient>> = all_ingredients
-                .iter()
-                .combinations(k as usize)
-                .map(|combo| combo.iter().map(|&ingredient| ingredient.clone()).collect())
-                .collect();
-            let filtered_combinations = filter_combinations(combinations);
-            simulate_combinations(&filtered_combinations, simulate_config)
+            let indices = CoveringCombinations::new(
+                representatives.len(),
+                k as usize,
+                repeated_ingredients,
+                &carriers,
+            );
+            let mut intrinsics = Vec::new();
+            for batch in &indices.chunks(ENUMERATE_BATCH_SIZE) {
+                if cancel.is_cancelled() {
+                    break;
+                }
+                let batch: Vec<Vec<usize>> = batch.collect();
+                let batch_size = batch.len() as u64;
+                let combinations: Vec<(Vec<usize>, PartCounts)> = batch
+                    .into_iter()
+                    .map(|indices| {
+                        let counts: PartCounts = indices.iter().map(|&i| part_counts[i]).sum();
+                        (indices, counts)
+                    })
+                    .collect();
+                let filtered_combinations = filter_combinations(
+                    combinations,
+                    &representatives,
+                    repeated_ingredients,
+                    &enumerate_config.filters,
+                );
+                intrinsics.extend(analyse_combinations(
+                    &filtered_combinations,
+                    &representatives,
+                    &enumerate_config.filters,
+                    memo.as_ref(),
+                    cancel,
+                ));
+                progress.advanced(Stage::Enumerate, batch_size);
+            }
+            intrinsics
         })
-        .collect()
+        .map(|intrinsics| expand_alternatives(intrinsics, &alternatives))
+        .collect();
+
+    if cancel.is_cancelled() {
+        return Err(Cancelled);
+    }
+    progress.stage_finished(Stage::Enumerate);
+    Ok(intrinsics)
+}
+
+/// Number of ways to choose k items from n.
+fn count_combinations(n: u64, k: u64) -> u64 {
+    if k > n {
+        return 0;
+    }
+    (0..k).fold(1, |count, i| count * (n - i) / (i + 1))
+}
+
+/// Number of candidate combinations of k ingredients among n, with or
+/// without repeats.
+fn count_candidates(n: u64, k: u64, repeated_ingredients: bool) -> u64 {
+    if repeated_ingredients && n > 0 {
+        count_combinations(n + k - 1, k)
+    } else {
+        count_combinations(n, k)
+    }
 }
 
-pub fn filter_combinations(combinations: Vec<Vec<Ingredient>>) -> Vec<Vec<Ingredient>> {
+/// Number of candidate combinations of k among n ingredients that include a
+/// carrier of every required part, by inclusion and exclusion over the parts.
+fn count_covering(n: usize, carriers: &[Vec<bool>], k: u64, repeated_ingredients: bool) -> u64 {
+    let mut covering: i64 = 0;
+    for missing in 0..1usize << carriers.len() {
+        // Ingredients carrying none of the parts in the missing set.
+        let remaining = (0..n)
+            .filter(|&i| {
+                carriers
+                    .iter()
+                    .enumerate()
+                    .all(|(j, carried)| missing & (1 << j) == 0 || !carried[i])
+            })
+            .count();
+        let count = count_candidates(remaining as u64, k, repeated_ingredients) as i64;
+        if missing.count_ones() % 2 == 0 {
+            covering += count;
+        } else {
+            covering -= count;
+        }
+    }
+    covering as u64
+}
+
+/// Combinations of k indices below n, in the order of
+/// `Itertools::combinations` (or `combinations_with_replacement`), that
+/// include an index carrying each required part. Branches that can no longer
+/// reach a carrier of a missing part are cut instead of generated.
+struct CoveringCombinations<'a> {
+    n: usize,
+    k: usize,
+    repeated: bool,
+    carriers: &'a [Vec<bool>],
+    combination: Vec<usize>,
+    /// Next index to try at the position after the combination so far.
+    cursor: usize,
+}
+
+impl<'a> CoveringCombinations<'a> {
+    fn new(n: usize, k: usize, repeated: bool, carriers: &'a [Vec<bool>]) -> Self {
+        CoveringCombinations {
+            n,
+            k,
+            repeated,
+            carriers,
+            combination: Vec::with_capacity(k),
+            cursor: 0,
+        }
+    }
+
+    /// Whether the combination so far, extended by index i, can still
+    /// include a carrier of every part.
+    fn can_cover(&self, i: usize) -> bool {
+        let remaining = self.k - self.combination.len() - 1;
+        let next = if self.repeated { i } else { i + 1 };
+        self.carriers.iter().all(|carried| {
+            carried[i]
+                || self.combination.iter().any(|&j| carried[j])
+                || (remaining > 0 && carried[next.min(self.n)..].contains(&true))
+        })
+    }
+}
+
+impl Iterator for CoveringCombinations<'_> {
+    type Item = Vec<usize>;
+
+    fn next(&mut self) -> Option<Vec<usize>> {
+        loop {
+            if self.cursor >= self.n {
+                // Every index was tried at this position, so move the one
+                // before it on.
+                let last = self.combination.pop()?;
+                self.cursor = last + 1;
+                continue;
+            }
+            let i = self.cursor;
+            if !self.can_cover(i) {
+                self.cursor += 1;
+                continue;
+            }
+            self.combination.push(i);
+            if self.combination.len() == self.k {
+                let combination = self.combination.clone();
+                self.combination.pop();
+                self.cursor = i + 1;
+                return Some(combination);
+            }
+            self.cursor = if self.repeated { i } else { i + 1 };
+        }
+    }
+}
+
+/// Restore every equivalent process chain for the ingredients of a recipe.
+pub(crate) fn expand_alternatives(
+    mut intrinsics: RecipeIntrinsics,
+    alternatives: &HashMap<Ingredient, Vec<IngredientProcess>>,
+) -> RecipeIntrinsics {
+    if alternatives.is_empty() {
+        // Analysis already lists each ingredient's own chain.
+        return intrinsics;
+    }
+    intrinsics.alternatives = intrinsics
+        .ingredients
+        .iter()
+        .map(|ingredient| match alternatives.get(ingredient) {
+            Some(processes) => processes.clone(),
+            None => vec![ingredient.process.clone()],
+        })
+        .collect();
+    intrinsics
+}
+
+/// Keep the combinations, given as indices into the ingredients with their
+/// summed part counts, that pass the filters on ingredients and parts.
+pub fn filter_combinations(
+    combinations: Vec<(Vec<usize>, PartCounts)>,
+    ingredients: &[Ingredient],
+    repeated_ingredients: bool,
+    filters: &[CandidateFilter],
+) -> Vec<(Vec<usize>, PartCounts)> {
     combinations
         .into_iter()
-        .filter(|combination| {
-            is_combination_valid(combination) && is_combination_reasonable(combination)
+        .filter(|(indices, counts)| {
+            (repeated_ingredients || are_keys_distinct(indices.iter().map(|&i| ingredients[i].key)))
+                && is_combination_reasonable(counts, filters)
         })
         .collect()
 }
 
-pub fn simulate_combinations(
-    combinations: &Vec<Vec<Ingredient>>,
-    simulate_config: &SimulateConfig,
-) -> Vec<Recipe> {
+/// Analyse combinations, given as indices into the ingredients with their
+/// summed part counts, sharing the analysis of identical counts through the
+/// memo if given. Ingredient lists are only built for recipes that pass the
+/// filters.
+pub fn analyse_combinations(
+    combinations: &[(Vec<usize>, PartCounts)],
+    ingredients: &[Ingredient],
+    filters: &[CandidateFilter],
+    memo: Option<&AnalysisMemo>,
+    cancel: &CancellationToken,
+) -> Vec<RecipeIntrinsics> {
     combinations
         .into_par_iter()
-        .filter_map(|combination| {
-            let local_combination: Vec<_> = combination.into_iter().cloned().collect();
-            let recipe = simulate::simulate(local_combination.as_slice(), &simulate_config)?;
-            if !is_recipe_reasonable(&recipe) {
+        .filter_map(|(indices, counts)| {
+            if cancel.is_cancelled() {
                 return None;
             }
-            Some(recipe)
+            let (has_herb, has_mushroom) =
+                simulate::ingredient_kinds(indices.iter().map(|&i| &ingredients[i]));
+            let intrinsics = match memo {
+                Some(memo) => {
+                    simulate::analyse_part_counts_memoised(counts, has_herb, has_mushroom, memo)
+                }
+                None => simulate::analyse_part_counts(counts, has_herb, has_mushroom),
+            }?;
+            if !is_intrinsics_reasonable(&intrinsics, filters) {
+                return None;
+            }
+            let combination = indices.iter().map(|&i| ingredients[i].clone()).collect();
+            Some(simulate::with_ingredients(intrinsics, combination))
         })
         .collect()
 }
 
-pub fn recommend(possible_recipes: Vec<Recipe>, config: &RecommendConfig) -> Vec<Recipe> {
+/// Remove recipes that can never improve on another candidate: same potion
+/// kind, a subset (or equal set) of its ingredients and no better appeal,
+/// potency or revenue. Returns the remaining recipes and how many were removed.
+///
+/// Ingredients are compared with their counts, so a recipe using an
+/// ingredient more than once only dominates recipes that use it at least as
+/// many times.
+pub fn prune_dominated(possible_recipes: Vec<Recipe>) -> (Vec<Recipe>, usize) {
+    let counts: Vec<Vec<(IngredientKey, usize)>> =
+        possible_recipes.iter().map(ingredient_counts).collect();
+
+    // Only recipes of the same potion kind are compared.
+    let mut kinds: HashMap<PotionKindKey, Vec<usize>> = HashMap::new();
+    for (i, recipe) in possible_recipes.iter().enumerate() {
+        kinds
+            .entry(recipe.potion_kind_key.clone())
+            .or_default()
+            .push(i);
+    }
+
+    let dominated: Vec<bool> = (0..possible_recipes.len())
+        .into_par_iter()
+        .map(|i| {
+            let recipe = &possible_recipes[i];
+            kinds[&recipe.potion_kind_key].iter().any(|&j| {
+                let other = &possible_recipes[j];
+                if i == j {
+                    return false;
+                }
+                let subset = is_sub_multiset(&counts[j], &counts[i]);
+                // The appeal stage leaves priced bulk offers out, so they
+                // only stand in for each other.
+                let comparable = !is_priced_bulk(other) || is_priced_bulk(recipe);
+                let no_worse = other.overall_appeal >= recipe.overall_appeal
+                    && other.overall_potency >= recipe.overall_potency
+                    && other.revenue >= recipe.revenue;
+                // Among identical candidates keep the first.
+                let strictly_better = counts[j] != counts[i]
+                    || other.overall_appeal > recipe.overall_appeal
+                    || other.overall_potency > recipe.overall_potency
+                    || other.revenue > recipe.revenue
+                    || j < i;
+                comparable && subset && no_worse && strictly_better
+            })
+        })
+        .collect();
+
+    let pruned = dominated
+        .iter()
+        .filter(|&&is_dominated| is_dominated)
+        .count();
+    let remaining = possible_recipes
+        .into_iter()
+        .zip(dominated)
+        .filter(|(_, is_dominated)| !is_dominated)
+        .map(|(recipe, _)| recipe)
+        .collect();
+
+    (remaining, pruned)
+}
+
+/// How many times the recipe uses each of its ingredients, sorted by key.
+fn ingredient_counts(recipe: &Recipe) -> Vec<(IngredientKey, usize)> {
+    recipe
+        .ingredients
+        .iter()
+        .map(|ingredient| ingredient.key)
+        .sorted()
+        .dedup_with_count()
+        .map(|(uses, key)| (key, uses))
+        .collect()
+}
+
+/// Whether every use of an ingredient in `inner` is matched by a use in
+/// `outer`.
+fn is_sub_multiset(inner: &[(IngredientKey, usize)], outer: &[(IngredientKey, usize)]) -> bool {
+    inner.iter().all(|(key, uses)| {
+        match outer.binary_search_by_key(key, |&(outer_key, _)| outer_key) {
+            Ok(i) => outer[i].1 >= *uses,
+            Err(_) => false,
+        }
+    })
+}
+
+pub fn recommend(
+    possible_recipes: Vec<Recipe>,
+    config: &RecommendConfig,
+    progress: &dyn ProgressSink,
+    cancel: &CancellationToken,
+) -> Result<Vec<Recipe>, Cancelled> {
+    let total = Some(possible_recipes.len() as u64);
+
+    progress.stage_started(Stage::MaximiseRecipes, total);
     let recipe_count = maximise_recipes(
         &possible_recipes,
         &config.available_ingredients,
         config.utilisation,
         &config.potions,
     );
+    progress.stage_finished(Stage::MaximiseRecipes);
 
+    if cancel.is_cancelled() {
+        return Err(Cancelled);
+    }
+
+    progress.stage_started(Stage::MaximiseAppeal, total);
     let appeal = maximise_appeal(
         &possible_recipes,
         &config.available_ingredients,
@@ -79,64 +489,534 @@ pub fn recommend(possible_recipes: Vec<Recipe>, config: &RecommendConfig) -> Vec
         &config.potions,
         recipe_count,
     );
+    progress.stage_finished(Stage::MaximiseAppeal);
+
+    if cancel.is_cancelled() {
+        return Err(Cancelled);
+    }
 
-    maximise_potency(
+    progress.stage_started(Stage::MaximisePotency, total);
+    let recipes = maximise_potency(
         &possible_recipes,
         &config.available_ingredients,
         config.utilisation,
         &config.potions,
         recipe_count,
         appeal,
-    )
+    );
+    progress.stage_finished(Stage::MaximisePotency);
+
+    Ok(recipes)
 }
 
 /// Validate a combination of ingredienst is a possible recipe.
-fn is_combination_valid(combination: &Vec<Ingredient>) -> bool {
-    // TODO: This count shouldn't be hard coded here.
-    let mut keys = [false; 16];
-
-    // If any single ingredient appears more than once
-    for ingredient in combination {
-        let index = ingredient.key as usize;
-        if keys[index] {
+pub(crate) fn is_combination_valid(combination: &[Ingredient], repeated_ingredients: bool) -> bool {
+    repeated_ingredients || are_keys_distinct(combination.iter().map(|ingredient| ingredient.key))
+}
+
+/// Whether no ingredient appears more than once.
+fn are_keys_distinct<I: Iterator<Item = IngredientKey>>(keys: I) -> bool {
+    let mut seen = vec![false; INGREDIENTS.0.len()];
+    for key in keys {
+        let index = key as usize;
+        if seen[index] {
             return false;
         }
-        keys[index] = true;
+        seen[index] = true;
     }
-
     true
 }
 
-fn is_combination_reasonable(combination: &Vec<Ingredient>) -> bool {
+pub(crate) fn is_combination_reasonable(counts: &PartCounts, filters: &[CandidateFilter]) -> bool {
     // If any ingredient contains an impurity.
-    let parts = collect_parts(combination.as_slice());
-    return !parts.contains(&IngredientPart::Impurity);
+    !filters.contains(&CandidateFilter::NoImpurity) || counts.get(&IngredientPart::Impurity) == 0
 }
 
-fn is_recipe_reasonable(recipe: &Recipe) -> bool {
-    // If the recipe overall appeal is negative.
-    if recipe.overall_appeal < 0 {
-        return false;
+pub(crate) fn is_intrinsics_reasonable(
+    intrinsics: &RecipeIntrinsics,
+    filters: &[CandidateFilter],
+) -> bool {
+    if !filters.contains(&CandidateFilter::MatchingToxicity) {
+        return true;
     }
 
     // If the recipe toxicity and toxicity appeal do not match.
-    let potion_kind = POTION_KINDS.get_by_key(&recipe.potion_kind_key);
+    let potion_kind = POTION_KINDS.get_by_key(&intrinsics.potion_kind_key);
     match potion_kind.toxicity_effect {
         ToxicityEffect::ToxicPositive => {
-            if matches!(recipe.overall_toxicity, OverallToxicity::Antitoxic)
-                || matches!(recipe.overall_toxicity, OverallToxicity::Veryantitoxic)
+            if matches!(intrinsics.overall_toxicity, OverallToxicity::Antitoxic)
+                || matches!(intrinsics.overall_toxicity, OverallToxicity::Veryantitoxic)
             {
                 return false;
             }
         }
         ToxicityEffect::ToxicNegative => {
-            if matches!(recipe.overall_toxicity, OverallToxicity::Toxic)
-                || matches!(recipe.overall_toxicity, OverallToxicity::VeryToxic)
+            if matches!(intrinsics.overall_toxicity, OverallToxicity::Toxic)
+                || matches!(intrinsics.overall_toxicity, OverallToxicity::VeryToxic)
             {
                 return false;
             }
         }
     }
 
-    return true;
+    true
+}
+
+pub(crate) fn is_recipe_reasonable(recipe: &Recipe, filters: &[CandidateFilter]) -> bool {
+    filters.iter().all(|filter| match filter {
+        CandidateFilter::MinAppeal(min_appeal) => recipe.overall_appeal >= *min_appeal,
+        CandidateFilter::MinPotency(min_potency) => recipe.overall_potency >= *min_potency,
+        CandidateFilter::NoImpurity | CandidateFilter::MatchingToxicity => true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        enumerate::default_filters,
+        models::{BrandingCategory, IngredientKey, Pricing, Process},
+        process::{VariantTable, PROCESS_RULES, PROCESS_VARIANTS},
+        progress::NoProgress,
+        recommend::{
+            ingredient_uses, maximise_appeal, maximise_potency, maximise_recipes, AlchemistRoster,
+            BrandingCounts, IngredientCounts, MarketConditions,
+        },
+        simulate::{analyse, score_offers, PriceModel},
+        testdata::{enumerate_config, INGREDIENT_COMBINATIONS},
+    };
+
+    use super::*;
+
+    fn objectives(
+        recipes: &Vec<Recipe>,
+        available_ingredients: &IngredientCounts,
+    ) -> (i32, i32, i32) {
+        let count = maximise_recipes(recipes, available_ingredients, 3, &[]);
+        let appeal = maximise_appeal(recipes, available_ingredients, 3, &[], count);
+        let potency: i32 = maximise_potency(recipes, available_ingredients, 3, &[], count, appeal)
+            .iter()
+            .map(|recipe| recipe.overall_potency)
+            .sum();
+        (count, appeal, potency)
+    }
+
+    #[test]
+    fn test_enumerate_potion_matches_full_enumeration() {
+        let enumerate_config = enumerate_config(
+            &[
+                IngredientKey::Catnip,
+                IngredientKey::Lupine,
+                IngredientKey::Thyme,
+                IngredientKey::Wormwood,
+                IngredientKey::Sage,
+            ],
+            3,
+            vec![Process::Crush, Process::Blanch, Process::Ferment],
+        );
+        let simulate_config = SimulateConfig::default();
+
+        let mut expected: Vec<Recipe> = enumerate_and_simulate(
+            &enumerate_config,
+            &simulate_config,
+            &NoProgress,
+            &CancellationToken::new(),
+        )
+        .unwrap()
+        .into_iter()
+        .filter(|recipe| recipe.potion_kind_key == PotionKindKey::Speed)
+        .collect();
+        let mut recipes = enumerate_and_simulate_potion(
+            &PotionKindKey::Speed,
+            &enumerate_config,
+            &simulate_config,
+            &NoProgress,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+
+        assert!(!recipes.is_empty());
+        assert!(recipes.windows(2).all(|pair| {
+            (pair[0].overall_appeal, pair[0].overall_potency)
+                >= (pair[1].overall_appeal, pair[1].overall_potency)
+        }));
+
+        expected.sort_by(|a, b| a.ingredients.cmp(&b.ingredients));
+        recipes.sort_by(|a, b| a.ingredients.cmp(&b.ingredients));
+        assert_eq!(recipes, expected);
+    }
+
+    #[test]
+    fn test_covering_combinations() {
+        let carriers = vec![
+            vec![false, true, false, false, true, false],
+            vec![true, true, false, false, false, false],
+        ];
+        let covers = |combination: &Vec<usize>| {
+            carriers
+                .iter()
+                .all(|carried| combination.iter().any(|&i| carried[i]))
+        };
+
+        for k in 1..=4 {
+            let expected: Vec<Vec<usize>> = (0..6).combinations(k).filter(covers).collect();
+            let found: Vec<Vec<usize>> =
+                CoveringCombinations::new(6, k, false, &carriers).collect();
+            assert_eq!(found, expected);
+            assert_eq!(
+                count_covering(6, &carriers, k as u64, false),
+                expected.len() as u64
+            );
+
+            let expected: Vec<Vec<usize>> = (0..6)
+                .combinations_with_replacement(k)
+                .filter(covers)
+                .collect();
+            let found: Vec<Vec<usize>> = CoveringCombinations::new(6, k, true, &carriers).collect();
+            assert_eq!(found, expected);
+            assert_eq!(
+                count_covering(6, &carriers, k as u64, true),
+                expected.len() as u64
+            );
+        }
+
+        // Without required parts every combination is generated.
+        let all: Vec<Vec<usize>> = CoveringCombinations::new(5, 3, false, &[]).collect();
+        assert_eq!(all, (0..5).combinations(3).collect::<Vec<_>>());
+        assert_eq!(count_covering(5, &[], 3, false), count_combinations(5, 3));
+        assert_eq!(
+            count_covering(5, &[], 3, true),
+            count_candidates(5, 3, true)
+        );
+    }
+
+    #[test]
+    fn test_enumerate_cancelled() {
+        let enumerate_config =
+            enumerate_config(&[IngredientKey::Catnip, IngredientKey::Lupine], 2, vec![]);
+        let simulate_config = SimulateConfig::default();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let result =
+            enumerate_and_simulate(&enumerate_config, &simulate_config, &NoProgress, &cancel);
+
+        assert_eq!(result, Err(Cancelled));
+    }
+
+    #[test]
+    fn test_enumerate_collapses_equivalent_chains() {
+        // Drying keeps the same parts as crushing under these rules.
+        let mut rules = PROCESS_RULES.clone();
+        for cut in rules.cuts.iter_mut() {
+            if cut.process == Process::Dry {
+                cut.kept = vec![1, 2];
+            }
+        }
+        let variants = Arc::new(VariantTable::from_rules(&rules));
+        let config = |processes: Vec<Process>| EnumerateConfig {
+            filters: vec![],
+            variants: variants.clone(),
+            ..enumerate_config(
+                &[
+                    IngredientKey::Catnip,
+                    IngredientKey::Lupine,
+                    IngredientKey::Thyme,
+                    IngredientKey::Sage,
+                ],
+                3,
+                processes,
+            )
+        };
+        let enumerate_with = |processes: Vec<Process>| {
+            enumerate(&config(processes), &NoProgress, &CancellationToken::new()).unwrap()
+        };
+
+        let crushed = enumerate_with(vec![Process::Crush]);
+        let collapsed = enumerate_with(vec![Process::Crush, Process::Dry]);
+
+        // Dried variants stand in for nothing new, so no recipe is added.
+        assert!(collapsed.iter().any(|intrinsics| intrinsics
+            .ingredients
+            .iter()
+            .any(|ingredient| ingredient.process == IngredientProcess::Crushed)));
+        assert_eq!(collapsed.len(), crushed.len());
+        for intrinsics in &collapsed {
+            for (ingredient, processes) in
+                intrinsics.ingredients.iter().zip(&intrinsics.alternatives)
+            {
+                let expected = match ingredient.process {
+                    IngredientProcess::Crushed => {
+                        vec![IngredientProcess::Crushed, IngredientProcess::Dried]
+                    }
+                    _ => vec![ingredient.process.clone()],
+                };
+                assert_eq!(processes, &expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_analyse_combinations_memoised() {
+        let ingredients: Vec<Ingredient> = [
+            IngredientKey::Catnip,
+            IngredientKey::Lupine,
+            IngredientKey::Sage,
+            IngredientKey::Flyagaric,
+        ]
+        .iter()
+        .map(|key| INGREDIENTS.get_by_key(key).clone())
+        .collect();
+        let combinations: Vec<(Vec<usize>, PartCounts)> = (0..ingredients.len())
+            .combinations(2)
+            .map(|indices| {
+                let counts = indices
+                    .iter()
+                    .map(|&i| PartCounts::of(&ingredients[i]))
+                    .sum();
+                (indices, counts)
+            })
+            .collect();
+        let filters = default_filters();
+        let cancel = CancellationToken::new();
+
+        let memo = AnalysisMemo::new();
+        let memoised =
+            analyse_combinations(&combinations, &ingredients, &filters, Some(&memo), &cancel);
+        let direct = analyse_combinations(&combinations, &ingredients, &filters, None, &cancel);
+
+        assert!(!memoised.is_empty());
+        assert_eq!(memoised, direct);
+        // Both match analysing each combination of ingredients on its own.
+        let expected: Vec<RecipeIntrinsics> = combinations
+            .iter()
+            .filter_map(|(indices, _)| {
+                let combination: Vec<Ingredient> =
+                    indices.iter().map(|&i| ingredients[i].clone()).collect();
+                simulate::analyse(&combination)
+            })
+            .filter(|intrinsics| is_intrinsics_reasonable(intrinsics, &filters))
+            .collect();
+        assert_eq!(direct, expected);
+        assert!(memo.len() <= combinations.len());
+    }
+
+    #[test]
+    fn test_prune_dominated_keeps_optimum() {
+        let ingredients = vec![
+            IngredientKey::Catnip,
+            IngredientKey::Lupine,
+            IngredientKey::Sage,
+            IngredientKey::Thyme,
+            IngredientKey::Wizards,
+            IngredientKey::Anise,
+        ];
+        let enumerate_config = enumerate_config(
+            &ingredients,
+            3,
+            vec![Process::Crush, Process::Dry, Process::Ferment],
+        );
+        let simulate_config = SimulateConfig::default();
+        let available_ingredients: IngredientCounts =
+            ingredients.into_iter().map(|key| (key, 1)).collect();
+
+        let recipes = enumerate_and_simulate(
+            &enumerate_config,
+            &simulate_config,
+            &NoProgress,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+        let (pruned_recipes, pruned) = prune_dominated(recipes.clone());
+
+        assert!(pruned > 0);
+        assert_eq!(pruned_recipes.len() + pruned, recipes.len());
+        assert_eq!(
+            objectives(&recipes, &available_ingredients),
+            objectives(&pruned_recipes, &available_ingredients)
+        );
+    }
+
+    #[test]
+    fn test_enumerate_repeated_ingredients() {
+        let ingredients = vec![
+            IngredientKey::Catnip,
+            IngredientKey::Lupine,
+            IngredientKey::Sage,
+            IngredientKey::Thyme,
+            IngredientKey::Wizards,
+            IngredientKey::Anise,
+        ];
+        let enumerate_config = EnumerateConfig {
+            repeated_ingredients: true,
+            ..enumerate_config(&ingredients, 3, vec![Process::Crush, Process::Dry])
+        };
+        let simulate_config = SimulateConfig::default();
+        let recipes = enumerate_and_simulate(
+            &enumerate_config,
+            &simulate_config,
+            &NoProgress,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+        let has_repeat = |recipe: &Recipe| {
+            recipe
+                .ingredients
+                .iter()
+                .any(|ingredient| ingredient_uses(recipe, &ingredient.key) > 1)
+        };
+        assert!(recipes.iter().any(has_repeat));
+
+        let (pruned_recipes, _) = prune_dominated(recipes);
+
+        // Each use counts against the ingredients available.
+        let available_ingredients: IngredientCounts =
+            ingredients.iter().map(|&key| (key, 1)).collect();
+        let count = maximise_recipes(&pruned_recipes, &available_ingredients, 3, &[]);
+        let appeal = maximise_appeal(&pruned_recipes, &available_ingredients, 3, &[], count);
+        let selected = maximise_potency(
+            &pruned_recipes,
+            &available_ingredients,
+            3,
+            &[],
+            count,
+            appeal,
+        );
+        for key in &ingredients {
+            let uses: i32 = selected
+                .iter()
+                .map(|recipe| ingredient_uses(recipe, key))
+                .sum();
+            assert!(uses <= 3);
+        }
+    }
+
+    #[test]
+    fn test_candidate_filters() {
+        let enumerate_config = EnumerateConfig {
+            filters: vec![],
+            ..enumerate_config(
+                &[
+                    IngredientKey::Catnip,
+                    IngredientKey::Lupine,
+                    IngredientKey::Sage,
+                    IngredientKey::Thyme,
+                ],
+                3,
+                vec![],
+            )
+        };
+        let simulate_config = SimulateConfig::default();
+        let recipes = enumerate_and_simulate(
+            &enumerate_config,
+            &simulate_config,
+            &NoProgress,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+        let impure = |recipe: &Recipe| {
+            simulate::collect_parts(&recipe.ingredients).contains(&IngredientPart::Impurity)
+        };
+        assert!(recipes.iter().any(impure));
+
+        let defaults = enumerate_and_simulate(
+            &EnumerateConfig {
+                filters: default_filters(),
+                variants: PROCESS_VARIANTS.clone(),
+                ..enumerate_config
+            },
+            &simulate_config,
+            &NoProgress,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+        assert!(!defaults.iter().any(impure));
+        assert!(defaults.iter().all(|recipe| recipe.overall_appeal >= 0));
+        assert!(defaults.len() < recipes.len());
+
+        let max_potency = recipes
+            .iter()
+            .map(|recipe| recipe.overall_potency)
+            .max()
+            .unwrap();
+        let filters = [CandidateFilter::MinPotency(max_potency)];
+        let strongest: Vec<&Recipe> = recipes
+            .iter()
+            .filter(|recipe| is_recipe_reasonable(recipe, &filters))
+            .collect();
+        assert!(!strongest.is_empty());
+        assert!(strongest
+            .iter()
+            .all(|recipe| recipe.overall_potency == max_potency));
+    }
+
+    #[test]
+    fn test_recommend_weighs_bulk_revenue() {
+        let mut branding_counts = BrandingCounts::new();
+        branding_counts.insert(BrandingCategory::Bulk, 5);
+        // Speed, Slow and Mana: one recipe for each department, each offered
+        // at both pricings.
+        let offers = |bulk_price_factor: f64| -> Vec<Recipe> {
+            let simulate_config = SimulateConfig {
+                alchemists: AlchemistRoster::new(),
+                market_conditions: MarketConditions::new(),
+                branding_counts: branding_counts.clone(),
+                price_model: Some(PriceModel {
+                    price_per_potency: 1.0,
+                    bulk_price_factor,
+                }),
+            };
+            INGREDIENT_COMBINATIONS[12..15]
+                .iter()
+                .flat_map(|(ingredients, _)| {
+                    score_offers(&analyse(ingredients).unwrap(), &simulate_config)
+                })
+                .collect()
+        };
+        let config = RecommendConfig {
+            available_ingredients: [
+                IngredientKey::Catnip,
+                IngredientKey::Lupine,
+                IngredientKey::Sage,
+                IngredientKey::Wizards,
+            ]
+            .iter()
+            .map(|&key| (key, 1))
+            .collect(),
+            utilisation: 3,
+            potions: vec![],
+        };
+        let cancel = CancellationToken::new();
+
+        // Bulk pricing gains appeal, but not enough to make up for half the
+        // revenue.
+        let recipes = offers(0.5);
+        assert_eq!(recipes.len(), 6);
+        let selected = recommend(recipes, &config, &NoProgress, &cancel).unwrap();
+        assert_eq!(selected.len(), 3);
+        assert!(selected
+            .iter()
+            .all(|recipe| recipe.pricing == Pricing::Standard));
+
+        // Once it earns more as well, bulk pricing is chosen.
+        let selected = recommend(offers(1.2), &config, &NoProgress, &cancel).unwrap();
+        assert_eq!(selected.len(), 3);
+        assert!(selected
+            .iter()
+            .all(|recipe| recipe.pricing == Pricing::Bulk));
+    }
+
+    #[test]
+    fn test_is_sub_multiset() {
+        let catnip = (IngredientKey::Catnip, 1);
+        let catnip_twice = (IngredientKey::Catnip, 2);
+        let sage = (IngredientKey::Sage, 1);
+
+        assert!(is_sub_multiset(&[catnip], &[catnip, sage]));
+        assert!(is_sub_multiset(&[catnip], &[catnip_twice]));
+        assert!(is_sub_multiset(&[], &[sage]));
+        assert!(!is_sub_multiset(&[catnip_twice], &[catnip, sage]));
+        assert!(!is_sub_multiset(&[sage], &[catnip_twice]));
+    }
 }
@@ -1,5 +1,14 @@
-use crate::models::{Ingredient, IngredientKey, Process};
-use crate::process;
+use std::{collections::HashMap, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Ingredient, IngredientKey, IngredientProcess, Process};
+use crate::process::{self, VariantTable};
+
+/// Fewest ingredients in a recipe.
+pub const MIN_ARCANE_POWER: i64 = 2;
+/// Most ingredients in a recipe. Larger recipes take too long to enumerate.
+pub const MAX_ARCANE_POWER: i64 = 5;
 
 #[derive(Debug)]
 pub struct EnumerateConfig {
@@ -7,6 +16,49 @@ pub struct EnumerateConfig {
     pub arcane_power: i64,
     pub utilisation: i32,
     pub processes: Vec<Process>,
+    /// Allow a recipe to use the same ingredient more than once, under the
+    /// same or different process chains.
+    pub repeated_ingredients: bool,
+    /// Rules a combination, and the recipes it makes, must pass to be a
+    /// candidate.
+    pub filters: Vec<CandidateFilter>,
+    /// Processed variants of the catalogue under the rules of the game
+    /// release, `process::PROCESS_VARIANTS` unless other rules are loaded.
+    pub variants: Arc<VariantTable>,
+}
+
+/// A rule deciding whether a combination, or a recipe it makes, is a
+/// candidate for recommendation.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CandidateFilter {
+    /// Reject combinations containing an impurity.
+    NoImpurity,
+    /// Reject recipes whose toxicity works against the potion kind.
+    MatchingToxicity,
+    /// Reject recipes with less appeal, after market conditions.
+    MinAppeal(i32),
+    /// Reject recipes with less potency.
+    MinPotency(i32),
+}
+
+impl CandidateFilter {
+    /// Whether the filter applies while enumerating, before recipes are
+    /// scored against the market.
+    pub fn applies_to_intrinsics(&self) -> bool {
+        matches!(
+            self,
+            CandidateFilter::NoImpurity | CandidateFilter::MatchingToxicity
+        )
+    }
+}
+
+/// The filters applied unless configured otherwise.
+pub fn default_filters() -> Vec<CandidateFilter> {
+    vec![
+        CandidateFilter::NoImpurity,
+        CandidateFilter::MatchingToxicity,
+        CandidateFilter::MinAppeal(0),
+    ]
 }
 
 pub fn permute_ingredient(ingredient: &Ingredient, processes: &Vec<Process>) -> Vec<Ingredient> {
@@ -55,12 +107,155 @@ pub fn permute_ingredient(ingredient: &Ingredient, processes: &Vec<Process>) ->
     result
 }
 
+/// Every variant of the catalogue ingredients reachable with the processes,
+/// read from the variant table.
 pub fn permute_ingredients(
+    variants: &VariantTable,
     ingredients: &[&Ingredient],
-    processes: &Vec<Process>,
+    processes: &[Process],
 ) -> Vec<Ingredient> {
     ingredients
         .iter()
-        .flat_map(|ing| permute_ingredient(ing, &processes))
+        .flat_map(|ing| variants.permutations(&ing.key, processes))
+        .cloned()
         .collect()
 }
+
+/// A processed ingredient standing in for every process chain of the same
+/// ingredient that yields an identical multiset of parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IngredientClass {
+    pub representative: Ingredient,
+    pub processes: Vec<IngredientProcess>,
+}
+
+/// Group processed ingredients that only differ by process chain but yield
+/// the same parts, keeping the first variant seen as representative. Classes
+/// are read from the variant table, so under rules where every chain gives
+/// different parts each ingredient is its own class.
+pub fn collapse_equivalent_ingredients(
+    variants: &VariantTable,
+    ingredients: Vec<Ingredient>,
+) -> Vec<IngredientClass> {
+    if variants.is_distinct() {
+        return ingredients
+            .into_iter()
+            .map(|ingredient| IngredientClass {
+                processes: vec![ingredient.process.clone()],
+                representative: ingredient,
+            })
+            .collect();
+    }
+
+    let mut classes: Vec<IngredientClass> = Vec::new();
+    let mut index: HashMap<(IngredientKey, usize), usize> = HashMap::new();
+
+    for ingredient in ingredients {
+        let class = variants.class(&ingredient.key, &ingredient.process);
+        let class_key = match class {
+            Some(class) => (ingredient.key, class),
+            // Not a variant of the table, so equivalent to nothing else.
+            None => {
+                classes.push(IngredientClass {
+                    processes: vec![ingredient.process.clone()],
+                    representative: ingredient,
+                });
+                continue;
+            }
+        };
+
+        match index.get(&class_key) {
+            Some(&position) => classes[position].processes.push(ingredient.process),
+            None => {
+                index.insert(class_key, classes.len());
+                classes.push(IngredientClass {
+                    processes: vec![ingredient.process.clone()],
+                    representative: ingredient,
+                });
+            }
+        }
+    }
+
+    classes
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        models::{traits::GetByKey, IngredientKey, Process, INGREDIENTS},
+        process::{VariantTable, PROCESS_RULES, PROCESS_VARIANTS},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_collapse_keeps_distinct_variants() {
+        let processes = vec![
+            Process::Crush,
+            Process::Blanch,
+            Process::Dry,
+            Process::Pickle,
+        ];
+        let sage = INGREDIENTS.get_by_key(&IngredientKey::Sage);
+        let variants = permute_ingredient(sage, &processes);
+
+        let classes = collapse_equivalent_ingredients(&PROCESS_VARIANTS, variants.clone());
+        let total: usize = classes.iter().map(|class| class.processes.len()).sum();
+
+        assert_eq!(classes.len(), variants.len());
+        assert_eq!(total, variants.len());
+
+        // No two chains of a catalogue ingredient give the same parts under
+        // the current rules.
+        assert!(PROCESS_VARIANTS.is_distinct());
+        let catalogue: Vec<Ingredient> = PROCESS_VARIANTS.iter().cloned().collect();
+        let classes = collapse_equivalent_ingredients(&PROCESS_VARIANTS, catalogue.clone());
+        assert_eq!(catalogue.len(), 137);
+        assert_eq!(classes.len(), catalogue.len());
+    }
+
+    #[test]
+    fn test_collapse_merges_identical_parts() {
+        // Drying keeps the same parts as crushing under these rules.
+        let mut rules = PROCESS_RULES.clone();
+        for cut in rules.cuts.iter_mut() {
+            if cut.process == Process::Dry {
+                cut.kept = vec![1, 2];
+            }
+        }
+        let table = VariantTable::from_rules(&rules);
+        let sage: Vec<Ingredient> = table
+            .permutations(&IngredientKey::Sage, &[Process::Crush, Process::Dry])
+            .cloned()
+            .collect();
+
+        let classes = collapse_equivalent_ingredients(&table, sage);
+
+        assert!(!table.is_distinct());
+        assert_eq!(classes.len(), 2);
+        assert_eq!(
+            &classes[1].representative,
+            table
+                .get(&IngredientKey::Sage, &IngredientProcess::Crushed)
+                .unwrap()
+        );
+        assert_eq!(
+            classes[1].processes,
+            vec![IngredientProcess::Crushed, IngredientProcess::Dried]
+        );
+
+        let catalogue: Vec<Ingredient> = table.iter().cloned().collect();
+        // Chains through drying merge with the same chains through crushing.
+        let dried = catalogue
+            .iter()
+            .filter(|ingredient| {
+                table
+                    .steps(&ingredient.key, &ingredient.process)
+                    .unwrap()
+                    .contains(&Process::Dry)
+            })
+            .count();
+        let classes = collapse_equivalent_ingredients(&table, catalogue.clone());
+        assert_eq!(classes.len(), catalogue.len() - dried);
+    }
+}
@@ -0,0 +1,513 @@
+use itertools::Itertools;
+
+use crate::{
+    core::{
+        is_combination_reasonable, is_combination_valid, is_intrinsics_reasonable,
+        is_recipe_reasonable, prune_dominated, recommend, score_recipes,
+    },
+    enumerate::{EnumerateConfig, MIN_ARCANE_POWER},
+    models::{
+        traits::GetByKey, Department, Ingredient, IngredientKey, IngredientProcess, Process,
+        Recipe, RecipeIntrinsics, POTION_KINDS,
+    },
+    progress::{CancellationToken, Cancelled, NoProgress},
+    recommend::{
+        ingredient_uses, maximise_fixed, IngredientCounts, Objective, RecommendConfig,
+        DEPARTMENT_CAPACITY,
+    },
+    simulate::{analyse_counts, score_offers, PartCounts, SimulateConfig},
+};
+
+/// Why a recipe is never a candidate for recommendation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    /// The recipe has fewer ingredients than any recipe, or more than the
+    /// arcane power allows.
+    IngredientCount(usize),
+    /// An ingredient is not in stock.
+    Unstocked(IngredientKey),
+    /// An ingredient's process chain cannot be reached under the process
+    /// rules.
+    UnreachableProcess(IngredientKey, IngredientProcess),
+    /// An ingredient needs a process that is not enabled.
+    DisabledProcess(Process),
+    /// An ingredient appears more than once.
+    RepeatedIngredient,
+    /// An ingredient contains an impurity.
+    Impurity,
+    /// The ingredients do not make a potion.
+    NoPotion,
+    /// The toxicity works against the potion kind.
+    Toxicity,
+    /// Every pricing of the recipe is below the minimum appeal or potency.
+    BelowThreshold,
+}
+
+/// A constraint the recipe competes for with the recommended recipes.
+#[derive(Debug, Clone)]
+pub enum Conflict {
+    /// Every use of the ingredient is taken by the recipes listed.
+    Ingredient {
+        key: IngredientKey,
+        available: i32,
+        used_by: Vec<Recipe>,
+    },
+    /// The recommendation already has a recipe of the potion kind.
+    PotionKind { recommended: Recipe },
+    /// The department is already full.
+    Department {
+        department: Department,
+        recipes: Vec<Recipe>,
+    },
+}
+
+/// How much worse the recommendation gets with the recipe forced in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Loss {
+    pub recipes: i32,
+    pub appeal: i32,
+    /// Potency, or revenue when recipes are priced.
+    pub potency: i32,
+}
+
+#[derive(Debug, Clone)]
+pub enum Explanation {
+    Rejected(Rejection),
+    /// The recipe is part of the recommendation.
+    Selected(Recipe),
+    /// The recipe is a candidate but a better recommendation leaves it out.
+    Excluded {
+        recipe: Recipe,
+        conflicts: Vec<Conflict>,
+        loss: Loss,
+        /// The best recommendation containing the recipe.
+        alternative: Vec<Recipe>,
+    },
+    /// No recommendation can contain the recipe.
+    Infeasible {
+        recipe: Recipe,
+        conflicts: Vec<Conflict>,
+    },
+}
+
+/// Explain why the recipe made of the ingredients is not part of the
+/// recommendation for the intrinsics enumerated with the configuration.
+///
+/// The recipe must use stocked ingredients and enabled processes, then goes
+/// through the same filters as enumeration and scoring. If it survives, the
+/// recommendation is solved again with it forced in (at its best pricing),
+/// and the constraints it shares with the original recommendation are
+/// reported along with the loss in recipes, appeal and potency.
+pub fn explain_exclusion(
+    ingredients: &[Ingredient],
+    intrinsics: &[RecipeIntrinsics],
+    simulate_config: &SimulateConfig,
+    config: &RecommendConfig,
+    enumerate_config: &EnumerateConfig,
+    cancel: &CancellationToken,
+) -> Result<Explanation, Cancelled> {
+    let offers = match candidate_offers(
+        ingredients,
+        &config.available_ingredients,
+        simulate_config,
+        enumerate_config,
+    ) {
+        Ok(offers) => offers,
+        Err(rejection) => return Ok(Explanation::Rejected(rejection)),
+    };
+
+    let (mut recipes, _) = prune_dominated(score_recipes(
+        intrinsics,
+        simulate_config,
+        &enumerate_config.filters,
+    ));
+    let recommendation = recommend(recipes.clone(), config, &NoProgress, cancel)?;
+    if let Some(recipe) = recommendation
+        .iter()
+        .find(|recipe| offers.iter().any(|offer| same_recipe(recipe, offer)))
+    {
+        return Ok(Explanation::Selected(recipe.clone()));
+    }
+
+    // Force in each pricing of the recipe, keeping the best outcome.
+    let first_offer = recipes.len();
+    recipes.extend(offers.iter().cloned());
+    let mut best: Option<(Loss, Vec<Recipe>)> = None;
+    for index in first_offer..recipes.len() {
+        if cancel.is_cancelled() {
+            return Err(Cancelled);
+        }
+        if let Some(alternative) = recommend_with(&recipes, config, index) {
+            let loss = Loss {
+                recipes: recommendation.len() as i32 - alternative.len() as i32,
+                appeal: total(&recommendation, |recipe| recipe.overall_appeal)
+                    - total(&alternative, |recipe| recipe.overall_appeal),
+                potency: total(&recommendation, value_of) - total(&alternative, value_of),
+            };
+            let better = match &best {
+                Some((best_loss, _)) => {
+                    (loss.recipes, loss.appeal, loss.potency)
+                        < (best_loss.recipes, best_loss.appeal, best_loss.potency)
+                }
+                None => true,
+            };
+            if better {
+                best = Some((loss, alternative));
+            }
+        }
+    }
+
+    Ok(match best {
+        Some((loss, alternative)) => {
+            let recipe = alternative
+                .iter()
+                .find(|recipe| offers.iter().any(|offer| same_recipe(recipe, offer)))
+                .cloned()
+                .unwrap_or_else(|| offers[0].clone());
+            Explanation::Excluded {
+                conflicts: conflicts(&recipe, &recommendation, config),
+                recipe,
+                loss,
+                alternative,
+            }
+        }
+        None => Explanation::Infeasible {
+            conflicts: conflicts(&offers[0], &recommendation, config),
+            recipe: offers[0].clone(),
+        },
+    })
+}
+
+/// Score the ingredients at every pricing, or report why they cannot be used
+/// or the filter that drops them.
+fn candidate_offers(
+    ingredients: &[Ingredient],
+    available_ingredients: &IngredientCounts,
+    simulate_config: &SimulateConfig,
+    enumerate_config: &EnumerateConfig,
+) -> Result<Vec<Recipe>, Rejection> {
+    if !(MIN_ARCANE_POWER..=enumerate_config.arcane_power).contains(&(ingredients.len() as i64)) {
+        return Err(Rejection::IngredientCount(ingredients.len()));
+    }
+    for ingredient in ingredients {
+        if available_ingredients.get(&ingredient.key).unwrap_or(&0) <= &0 {
+            return Err(Rejection::Unstocked(ingredient.key));
+        }
+        let steps = enumerate_config
+            .variants
+            .steps(&ingredient.key, &ingredient.process)
+            .ok_or_else(|| {
+                Rejection::UnreachableProcess(ingredient.key, ingredient.process.clone())
+            })?;
+        if let Some(process) = steps
+            .iter()
+            .find(|process| !enumerate_config.processes.contains(process))
+        {
+            return Err(Rejection::DisabledProcess(*process));
+        }
+    }
+
+    let filters = &enumerate_config.filters;
+    if !is_combination_valid(ingredients, enumerate_config.repeated_ingredients) {
+        return Err(Rejection::RepeatedIngredient);
+    }
+    let counts = PartCounts::of_all(ingredients);
+    if !is_combination_reasonable(&counts, filters) {
+        return Err(Rejection::Impurity);
+    }
+    let intrinsics = analyse_counts(ingredients, &counts).ok_or(Rejection::NoPotion)?;
+    if !is_intrinsics_reasonable(&intrinsics, filters) {
+        return Err(Rejection::Toxicity);
+    }
+
+    let offers: Vec<Recipe> = score_offers(&intrinsics, simulate_config)
+        .into_iter()
+        .filter(|offer| is_recipe_reasonable(offer, filters))
+        .collect();
+    if offers.is_empty() {
+        return Err(Rejection::BelowThreshold);
+    }
+    Ok(offers)
+}
+
+/// Lexicographic recommendation, as `core::recommend`, with the recipe at
+/// index forced in. None if no recommendation can contain it.
+fn recommend_with(
+    recipes: &[Recipe],
+    config: &RecommendConfig,
+    index: usize,
+) -> Option<Vec<Recipe>> {
+    let fixed = (index, true);
+    let (count, _) = maximise_fixed(recipes, config, Objective::Recipes, 0, None, fixed)?;
+    let (appeal, _) = maximise_fixed(recipes, config, Objective::Appeal, count, None, fixed)?;
+    let (_, selected) = maximise_fixed(
+        recipes,
+        config,
+        Objective::Potency,
+        count,
+        Some(appeal),
+        fixed,
+    )?;
+    Some(selected.into_iter().map(|i| recipes[i].clone()).collect())
+}
+
+/// The constraints of the recommendation that the recipe would also need.
+fn conflicts(
+    recipe: &Recipe,
+    recommendation: &[Recipe],
+    config: &RecommendConfig,
+) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+
+    for ingredient in recipe
+        .ingredients
+        .iter()
+        .unique_by(|ingredient| ingredient.key)
+    {
+        let available = config
+            .available_ingredients
+            .get(&ingredient.key)
+            .unwrap_or(&0)
+            * config.utilisation;
+        let used_by: Vec<Recipe> = recommendation
+            .iter()
+            .filter(|other| ingredient_uses(other, &ingredient.key) > 0)
+            .cloned()
+            .collect();
+        let used: i32 = used_by
+            .iter()
+            .map(|other| ingredient_uses(other, &ingredient.key))
+            .sum();
+        if used + ingredient_uses(recipe, &ingredient.key) > available {
+            conflicts.push(Conflict::Ingredient {
+                key: ingredient.key,
+                available,
+                used_by,
+            });
+        }
+    }
+
+    if let Some(recommended) = recommendation
+        .iter()
+        .find(|other| other.potion_kind_key == recipe.potion_kind_key)
+    {
+        conflicts.push(Conflict::PotionKind {
+            recommended: recommended.clone(),
+        });
+    }
+
+    let department = POTION_KINDS.get_by_key(&recipe.potion_kind_key).department;
+    let recipes: Vec<Recipe> = recommendation
+        .iter()
+        .filter(|other| POTION_KINDS.get_by_key(&other.potion_kind_key).department == department)
+        .cloned()
+        .collect();
+    if recipes.len() >= DEPARTMENT_CAPACITY {
+        conflicts.push(Conflict::Department {
+            department,
+            recipes,
+        });
+    }
+
+    conflicts
+}
+
+fn value_of(recipe: &Recipe) -> i32 {
+    recipe.revenue.unwrap_or(recipe.overall_potency)
+}
+
+fn total<F>(recipes: &[Recipe], score: F) -> i32
+where
+    F: Fn(&Recipe) -> i32,
+{
+    recipes.iter().map(score).sum()
+}
+
+/// Whether two recipes brew the same potion from the same ingredients at the
+/// same pricing. Ingredients match if either lists the other's process as
+/// an equivalent alternative, and each ingredient of one recipe is matched
+/// to a different ingredient of the other, so repeats must agree.
+fn same_recipe(a: &Recipe, b: &Recipe) -> bool {
+    if a.potion_kind_key != b.potion_kind_key
+        || a.pricing != b.pricing
+        || a.ingredients.len() != b.ingredients.len()
+    {
+        return false;
+    }
+    let mut matched = vec![false; b.ingredients.len()];
+    a.ingredients
+        .iter()
+        .zip(&a.alternatives)
+        .all(|(ingredient, processes)| {
+            let found = b
+                .ingredients
+                .iter()
+                .zip(&b.alternatives)
+                .enumerate()
+                .position(|(j, (other, other_processes))| {
+                    !matched[j]
+                        && ingredient.key == other.key
+                        && (processes.contains(&other.process)
+                            || other_processes.contains(&ingredient.process))
+                });
+            match found {
+                Some(j) => {
+                    matched[j] = true;
+                    true
+                }
+                None => false,
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        core::enumerate,
+        models::INGREDIENTS,
+        process::{process_ingredient, VariantTable, PROCESS_RULES, PROCESS_VARIANTS},
+        simulate::simulate,
+        testdata::{enumerate_config, INGREDIENT_COMBINATIONS, TEST_INGREDIENTS},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_explain_exclusion() {
+        let keys = TEST_INGREDIENTS.to_vec();
+        let enumerate_config = enumerate_config(&keys, 3, vec![Process::Crush, Process::Dry]);
+        let intrinsics =
+            enumerate(&enumerate_config, &NoProgress, &CancellationToken::new()).unwrap();
+        let simulate_config = SimulateConfig::default();
+        let config = RecommendConfig {
+            available_ingredients: keys.iter().map(|&key| (key, 1)).collect(),
+            utilisation: 3,
+            potions: vec![],
+        };
+        let explain = |ingredients: &[Ingredient]| {
+            explain_exclusion(
+                ingredients,
+                &intrinsics,
+                &simulate_config,
+                &config,
+                &enumerate_config,
+                &CancellationToken::new(),
+            )
+            .unwrap()
+        };
+        let ingredient = |key, processes: &Vec<Process>| {
+            process_ingredient(INGREDIENTS.get_by_key(&key), processes)
+        };
+
+        let thyme = ingredient(IngredientKey::Thyme, &vec![]);
+        assert!(matches!(
+            explain(&[thyme.clone(), thyme.clone()]),
+            Explanation::Rejected(Rejection::RepeatedIngredient)
+        ));
+
+        let mandrake = ingredient(IngredientKey::Mandrake, &vec![]);
+        assert!(matches!(
+            explain(&[thyme.clone(), mandrake]),
+            Explanation::Rejected(Rejection::Unstocked(IngredientKey::Mandrake))
+        ));
+        let pickled_sage = ingredient(IngredientKey::Sage, &vec![Process::Pickle]);
+        assert_eq!(
+            PROCESS_VARIANTS.steps(&pickled_sage.key, &pickled_sage.process),
+            Some(&[Process::Pickle][..])
+        );
+        assert!(matches!(
+            explain(&[thyme.clone(), pickled_sage]),
+            Explanation::Rejected(Rejection::DisabledProcess(Process::Pickle))
+        ));
+
+        // Every candidate is either recommended or loses out to it.
+        let (recipes, _) = prune_dominated(score_recipes(
+            &intrinsics,
+            &simulate_config,
+            &enumerate_config.filters,
+        ));
+        for recipe in recipes {
+            match explain(&recipe.ingredients) {
+                Explanation::Selected(_) => {}
+                Explanation::Excluded {
+                    conflicts, loss, ..
+                } => {
+                    assert!(!conflicts.is_empty());
+                    assert!((loss.recipes, loss.appeal, loss.potency) >= (0, 0, 0));
+                }
+                Explanation::Infeasible { conflicts, .. } => assert!(!conflicts.is_empty()),
+                Explanation::Rejected(rejection) => panic!("{:?} was rejected", rejection),
+            }
+        }
+    }
+
+    #[test]
+    fn test_candidate_offers_rejects_unusable_recipes() {
+        let keys = vec![
+            IngredientKey::Thyme,
+            IngredientKey::Sage,
+            IngredientKey::Catnip,
+        ];
+        // These rules have no way of crushing.
+        let mut rules = PROCESS_RULES.clone();
+        rules.cuts.retain(|cut| cut.process != Process::Crush);
+        let enumerate_config = EnumerateConfig {
+            variants: Arc::new(VariantTable::from_rules(&rules)),
+            ..enumerate_config(&keys, 2, vec![Process::Crush])
+        };
+        let simulate_config = SimulateConfig::default();
+        let available_ingredients: IngredientCounts = keys.iter().map(|&key| (key, 1)).collect();
+        let offers = |ingredients: &[Ingredient]| {
+            candidate_offers(
+                ingredients,
+                &available_ingredients,
+                &simulate_config,
+                &enumerate_config,
+            )
+        };
+        let raw = |key| INGREDIENTS.get_by_key(&key).clone();
+
+        assert_eq!(
+            offers(&[raw(IngredientKey::Thyme)]).unwrap_err(),
+            Rejection::IngredientCount(1)
+        );
+        assert_eq!(
+            offers(&keys.iter().map(|&key| raw(key)).collect::<Vec<_>>()).unwrap_err(),
+            Rejection::IngredientCount(3)
+        );
+
+        let crushed_sage = PROCESS_VARIANTS
+            .get(&IngredientKey::Sage, &IngredientProcess::Crushed)
+            .unwrap()
+            .clone();
+        assert_eq!(
+            offers(&[raw(IngredientKey::Thyme), crushed_sage]).unwrap_err(),
+            Rejection::UnreachableProcess(IngredientKey::Sage, IngredientProcess::Crushed)
+        );
+    }
+
+    #[test]
+    fn test_same_recipe_counts_repeats() {
+        let (ingredients, _) = &INGREDIENT_COMBINATIONS[0];
+        let simulate_config = SimulateConfig::default();
+        let recipe = simulate(ingredients, &simulate_config).unwrap();
+        let with = |ingredients: Vec<Ingredient>| Recipe {
+            alternatives: ingredients
+                .iter()
+                .map(|ingredient| vec![ingredient.process.clone()])
+                .collect(),
+            ingredients,
+            ..recipe.clone()
+        };
+        let (a, b) = (ingredients[0].clone(), ingredients[1].clone());
+
+        let twice_a = with(vec![a.clone(), a.clone(), b.clone()]);
+        let twice_b = with(vec![a.clone(), b.clone(), b.clone()]);
+        let reordered = with(vec![a.clone(), b, a]);
+        assert!(!same_recipe(&twice_a, &twice_b));
+        assert!(!same_recipe(&twice_b, &twice_a));
+        assert!(same_recipe(&twice_a, &reordered));
+    }
+}
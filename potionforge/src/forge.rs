@@ -0,0 +1,525 @@
+use std::{
+    error::Error,
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    core::{enumerate, prune_dominated, recommend, score_recipes},
+    enumerate::{
+        default_filters, CandidateFilter, EnumerateConfig, MAX_ARCANE_POWER, MIN_ARCANE_POWER,
+    },
+    models::{
+        traits::GetByKey, BrandingCategory, Department, IngredientKey, PotionKindKey, Process,
+        Recipe, POTION_KINDS,
+    },
+    process::{ProcessRules, VariantTable, PROCESS_VARIANTS},
+    progress::{CancellationToken, Cancelled, ProgressSink},
+    recommend::{
+        AlchemistRoster, BrandingCounts, IngredientCounts, MarketConditions, RecommendConfig,
+        DEPARTMENT_CAPACITY, MIN_UTILISATION,
+    },
+    scenario::{
+        recommend_scenarios, validate_scenarios, MarketScenario, ScenarioError, ScenarioObjective,
+        ScenarioRecommendation,
+    },
+    simulate::{PriceModel, SimulateConfig},
+};
+
+/// Builds the configuration of every stage from a single description, so
+/// settings shared between stages (such as utilisation) cannot disagree.
+///
+/// The portfolio has the most recipes, then the most appeal, then the most
+/// potency (or revenue when priced). The only other objective is the one
+/// weighing market scenarios, given with `scenarios`.
+///
+/// ```
+/// use potionforge::{
+///     forge::Forge,
+///     models::{IngredientKey, Process},
+///     progress::{CancellationToken, NoProgress},
+/// };
+///
+/// let pipeline = Forge::new()
+///     .ingredient(IngredientKey::Catnip, 1)
+///     .ingredient(IngredientKey::Lupine, 1)
+///     .ingredient(IngredientKey::Sage, 1)
+///     .ingredient(IngredientKey::Thyme, 1)
+///     .ingredient(IngredientKey::Wizards, 1)
+///     .ingredient(IngredientKey::Anise, 1)
+///     .processes(vec![Process::Crush, Process::Dry])
+///     .arcane_power(3)
+///     .build()?;
+/// let result = pipeline.run(&NoProgress, &CancellationToken::new())?;
+/// assert!(!result.portfolio.is_empty());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Forge {
+    ingredients: IngredientCounts,
+    processes: Vec<Process>,
+    arcane_power: i64,
+    utilisation: i32,
+    repeated_ingredients: bool,
+    filters: Vec<CandidateFilter>,
+    alchemists: AlchemistRoster,
+    market: MarketConditions,
+    branding: BrandingCounts,
+    prices: Option<PriceModel>,
+    potions: Vec<PotionKindKey>,
+    scenarios: Vec<MarketScenario>,
+    scenario_objective: ScenarioObjective,
+    process_rules: Option<ProcessRules>,
+}
+
+impl Default for Forge {
+    fn default() -> Self {
+        Forge {
+            ingredients: IngredientCounts::new(),
+            processes: Vec::new(),
+            arcane_power: 2,
+            utilisation: 3,
+            repeated_ingredients: false,
+            filters: default_filters(),
+            alchemists: AlchemistRoster::new(),
+            market: MarketConditions::new(),
+            branding: BrandingCounts::new(),
+            prices: None,
+            potions: Vec::new(),
+            scenarios: Vec::new(),
+            scenario_objective: ScenarioObjective::default(),
+            process_rules: None,
+        }
+    }
+}
+
+impl Forge {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the available ingredients.
+    pub fn ingredients(mut self, ingredients: IngredientCounts) -> Self {
+        self.ingredients = ingredients;
+        self
+    }
+
+    /// Make an ingredient available, replacing any previous count.
+    pub fn ingredient(mut self, key: IngredientKey, count: i32) -> Self {
+        self.ingredients.insert(key, count);
+        self
+    }
+
+    pub fn processes(mut self, processes: Vec<Process>) -> Self {
+        self.processes = processes;
+        self
+    }
+
+    /// Most ingredients in a recipe.
+    pub fn arcane_power(mut self, arcane_power: i64) -> Self {
+        self.arcane_power = arcane_power;
+        self
+    }
+
+    /// Number of recipes each available ingredient can be used in.
+    pub fn utilisation(mut self, utilisation: i32) -> Self {
+        self.utilisation = utilisation;
+        self
+    }
+
+    pub fn repeated_ingredients(mut self, repeated_ingredients: bool) -> Self {
+        self.repeated_ingredients = repeated_ingredients;
+        self
+    }
+
+    pub fn filters(mut self, filters: Vec<CandidateFilter>) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    pub fn alchemists(mut self, alchemists: AlchemistRoster) -> Self {
+        self.alchemists = alchemists;
+        self
+    }
+
+    pub fn market(mut self, market: MarketConditions) -> Self {
+        self.market = market;
+        self
+    }
+
+    pub fn branding(mut self, branding: BrandingCounts) -> Self {
+        self.branding = branding;
+        self
+    }
+
+    pub fn prices(mut self, prices: PriceModel) -> Self {
+        self.prices = Some(prices);
+        self
+    }
+
+    /// Potion kinds the portfolio must contain.
+    pub fn potions(mut self, potions: Vec<PotionKindKey>) -> Self {
+        self.potions = potions;
+        self
+    }
+
+    /// Recommend over market scenarios instead of the single market.
+    pub fn scenarios(
+        mut self,
+        scenarios: Vec<MarketScenario>,
+        objective: ScenarioObjective,
+    ) -> Self {
+        self.scenarios = scenarios;
+        self.scenario_objective = objective;
+        self
+    }
+
+    /// Process with the rules of a newer game release instead of the built
+    /// in ones.
+    pub fn process_rules(mut self, rules: ProcessRules) -> Self {
+        self.process_rules = Some(rules);
+        self
+    }
+
+    /// Check the whole configuration and derive the configuration of each
+    /// stage.
+    pub fn build(self) -> Result<Pipeline, ConfigError> {
+        if self.ingredients.is_empty() {
+            return Err(ConfigError::NoIngredients);
+        }
+        for &count in self.ingredients.values().chain(self.branding.values()) {
+            check_count(count)?;
+        }
+        check_arcane_power(self.arcane_power)?;
+        check_utilisation(self.utilisation)?;
+        for department in [
+            Department::Health,
+            Department::Sourcery,
+            Department::Provisions,
+        ] {
+            let potions = self
+                .potions
+                .iter()
+                .filter(|key| POTION_KINDS.get_by_key(key).department == department)
+                .count();
+            if potions > DEPARTMENT_CAPACITY {
+                return Err(ConfigError::TooManyPotions(department, potions));
+            }
+        }
+        if self.prices.is_none() && self.branding.get(&BrandingCategory::Bulk) > Some(&0) {
+            return Err(ConfigError::BulkWithoutPrices);
+        }
+        if !self.scenarios.is_empty() {
+            validate_scenarios(&self.scenarios).map_err(ConfigError::Scenarios)?;
+        }
+
+        Ok(Pipeline {
+            enumerate_config: EnumerateConfig {
+                ingredients: self.ingredients.keys().cloned().collect(),
+                arcane_power: self.arcane_power,
+                utilisation: self.utilisation,
+                processes: self.processes,
+                repeated_ingredients: self.repeated_ingredients,
+                filters: self.filters,
+                variants: match &self.process_rules {
+                    Some(rules) => Arc::new(VariantTable::from_rules(rules)),
+                    None => PROCESS_VARIANTS.clone(),
+                },
+            },
+            simulate_config: SimulateConfig {
+                alchemists: self.alchemists,
+                market_conditions: self.market,
+                branding_counts: self.branding,
+                price_model: self.prices,
+            },
+            recommend_config: RecommendConfig {
+                available_ingredients: self.ingredients,
+                utilisation: self.utilisation,
+                potions: self.potions,
+            },
+            scenarios: self.scenarios,
+            scenario_objective: self.scenario_objective,
+        })
+    }
+}
+
+/// Check an arcane power. Configuration files are checked with the same
+/// rules as `Forge`.
+pub(crate) fn check_arcane_power(arcane_power: i64) -> Result<i64, ConfigError> {
+    if !(MIN_ARCANE_POWER..=MAX_ARCANE_POWER).contains(&arcane_power) {
+        return Err(ConfigError::ArcanePower(arcane_power));
+    }
+    Ok(arcane_power)
+}
+
+pub(crate) fn check_utilisation(utilisation: i32) -> Result<i32, ConfigError> {
+    if utilisation < MIN_UTILISATION {
+        return Err(ConfigError::Utilisation(utilisation));
+    }
+    Ok(utilisation)
+}
+
+/// Check an ingredient or branding count.
+pub(crate) fn check_count(count: i32) -> Result<i32, ConfigError> {
+    if count < 0 {
+        return Err(ConfigError::NegativeCount(count));
+    }
+    Ok(count)
+}
+
+/// Why a `Forge` configuration cannot be run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    NoIngredients,
+    /// An ingredient or branding count below zero.
+    NegativeCount(i32),
+    /// Recipes have between two and five ingredients.
+    ArcanePower(i64),
+    Utilisation(i32),
+    /// More potions required in the department than it can hold.
+    TooManyPotions(Department, usize),
+    /// Bulk branding without a price model to weigh the revenue bulk pricing
+    /// loses against the appeal it gains.
+    BulkWithoutPrices,
+    /// The scenarios cannot be weighted by their probabilities.
+    Scenarios(ScenarioError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::NoIngredients => write!(f, "no ingredients are available"),
+            ConfigError::NegativeCount(count) => write!(f, "count {} is negative", count),
+            ConfigError::ArcanePower(arcane_power) => {
+                write!(
+                    f,
+                    "arcane power {} is outside {} to {}",
+                    arcane_power, MIN_ARCANE_POWER, MAX_ARCANE_POWER
+                )
+            }
+            ConfigError::Utilisation(utilisation) => {
+                write!(
+                    f,
+                    "utilisation {} is below {}",
+                    utilisation, MIN_UTILISATION
+                )
+            }
+            ConfigError::TooManyPotions(department, potions) => write!(
+                f,
+                "{} potions are required in {:?}, which holds at most {}",
+                potions, department, DEPARTMENT_CAPACITY
+            ),
+            ConfigError::BulkWithoutPrices => {
+                write!(f, "Bulk branding needs prices to weigh bulk pricing")
+            }
+            ConfigError::Scenarios(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// A validated configuration, ready to run.
+#[derive(Debug)]
+pub struct Pipeline {
+    enumerate_config: EnumerateConfig,
+    simulate_config: SimulateConfig,
+    recommend_config: RecommendConfig,
+    scenarios: Vec<MarketScenario>,
+    scenario_objective: ScenarioObjective,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineStage {
+    Enumerate,
+    Score,
+    Prune,
+    Recommend,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StageReport {
+    pub stage: PipelineStage,
+    pub duration: Duration,
+    /// Recipes (or intrinsics, when enumerating) left after the stage.
+    pub count: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ForgeResult {
+    pub portfolio: Vec<Recipe>,
+    /// The outcome in each scenario, when recommending over scenarios.
+    pub scenarios: Option<ScenarioRecommendation>,
+    /// Each stage that ran, in order. Scoring and pruning are part of the
+    /// recommendation over scenarios.
+    pub stages: Vec<StageReport>,
+}
+
+impl Pipeline {
+    pub fn enumerate_config(&self) -> &EnumerateConfig {
+        &self.enumerate_config
+    }
+
+    pub fn simulate_config(&self) -> &SimulateConfig {
+        &self.simulate_config
+    }
+
+    pub fn recommend_config(&self) -> &RecommendConfig {
+        &self.recommend_config
+    }
+
+    /// Enumerate, score, prune and recommend.
+    pub fn run(
+        &self,
+        progress: &dyn ProgressSink,
+        cancel: &CancellationToken,
+    ) -> Result<ForgeResult, Cancelled> {
+        let mut stages = Vec::new();
+        let mut report = |stage, started: Instant, count| {
+            stages.push(StageReport {
+                stage,
+                duration: started.elapsed(),
+                count,
+            })
+        };
+
+        let started = Instant::now();
+        let intrinsics = enumerate(&self.enumerate_config, progress, cancel)?;
+        report(PipelineStage::Enumerate, started, intrinsics.len());
+
+        if !self.scenarios.is_empty() {
+            let started = Instant::now();
+            let recommendation = recommend_scenarios(
+                &intrinsics,
+                &self.simulate_config,
+                &self.enumerate_config.filters,
+                &self.scenarios,
+                self.scenario_objective,
+                &self.recommend_config,
+                cancel,
+            )
+            .map_err(|error| match error {
+                ScenarioError::Cancelled => Cancelled,
+                // The scenarios were validated when the pipeline was built.
+                error => unreachable!("{}", error),
+            })?;
+            report(
+                PipelineStage::Recommend,
+                started,
+                recommendation.recipes.len(),
+            );
+            return Ok(ForgeResult {
+                portfolio: recommendation.recipes.clone(),
+                scenarios: Some(recommendation),
+                stages,
+            });
+        }
+
+        let started = Instant::now();
+        let recipes = score_recipes(
+            &intrinsics,
+            &self.simulate_config,
+            &self.enumerate_config.filters,
+        );
+        report(PipelineStage::Score, started, recipes.len());
+
+        let started = Instant::now();
+        let (recipes, _) = prune_dominated(recipes);
+        report(PipelineStage::Prune, started, recipes.len());
+
+        let started = Instant::now();
+        let portfolio = recommend(recipes, &self.recommend_config, progress, cancel)?;
+        report(PipelineStage::Recommend, started, portfolio.len());
+
+        Ok(ForgeResult {
+            portfolio,
+            scenarios: None,
+            stages,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{core::enumerate_and_simulate, progress::NoProgress};
+
+    use super::*;
+
+    #[test]
+    fn test_forge_matches_core() {
+        let forge = Forge::new()
+            .ingredient(IngredientKey::Catnip, 1)
+            .ingredient(IngredientKey::Lupine, 1)
+            .ingredient(IngredientKey::Sage, 1)
+            .ingredient(IngredientKey::Thyme, 1)
+            .ingredient(IngredientKey::Wizards, 1)
+            .ingredient(IngredientKey::Anise, 1)
+            .processes(vec![Process::Crush, Process::Dry])
+            .arcane_power(3);
+
+        assert_eq!(
+            forge.clone().utilisation(0).build().unwrap_err(),
+            ConfigError::Utilisation(0)
+        );
+        assert_eq!(
+            forge.clone().arcane_power(6).build().unwrap_err(),
+            ConfigError::ArcanePower(6)
+        );
+        assert_eq!(
+            Forge::new().build().unwrap_err(),
+            ConfigError::NoIngredients
+        );
+        assert_eq!(
+            forge
+                .clone()
+                .ingredient(IngredientKey::Sage, -1)
+                .build()
+                .unwrap_err(),
+            ConfigError::NegativeCount(-1)
+        );
+        let bulk = forge
+            .clone()
+            .branding([(BrandingCategory::Bulk, 1)].into_iter().collect());
+        assert_eq!(
+            bulk.clone().build().unwrap_err(),
+            ConfigError::BulkWithoutPrices
+        );
+        assert!(bulk
+            .prices(PriceModel {
+                price_per_potency: 1.0,
+                bulk_price_factor: 0.8,
+            })
+            .build()
+            .is_ok());
+
+        let cancel = CancellationToken::new();
+        let pipeline = forge.build().unwrap();
+        let result = pipeline.run(&NoProgress, &cancel).unwrap();
+
+        let recipes = enumerate_and_simulate(
+            pipeline.enumerate_config(),
+            pipeline.simulate_config(),
+            &NoProgress,
+            &cancel,
+        )
+        .unwrap();
+        let (recipes, _) = prune_dominated(recipes);
+        let expected =
+            recommend(recipes, pipeline.recommend_config(), &NoProgress, &cancel).unwrap();
+
+        assert_eq!(result.portfolio, expected);
+        let stages: Vec<PipelineStage> = result.stages.iter().map(|report| report.stage).collect();
+        assert_eq!(
+            stages,
+            vec![
+                PipelineStage::Enumerate,
+                PipelineStage::Score,
+                PipelineStage::Prune,
+                PipelineStage::Recommend
+            ]
+        );
+        assert_eq!(result.stages[3].count, result.portfolio.len());
+        assert!(result.stages[2].count <= result.stages[1].count);
+    }
+}
@@ -1,7 +1,17 @@
+pub mod cache;
+pub mod config;
 pub mod core;
 pub mod enumerate;
+pub mod explain;
+pub mod forge;
 pub mod models;
+pub mod plan;
 pub mod process;
+pub mod progress;
 pub mod recommend;
+pub mod repair;
+pub mod scenario;
+pub mod sensitivity;
 pub mod simulate;
+pub mod substitute;
 pub mod testdata;
@@ -1,8 +1,8 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use super::traits::{GetByKey, GetName, ToHumanReadable};
 
-#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum IngredientKey {
     Catnip,
     Lupine,
@@ -46,7 +46,7 @@ impl ToHumanReadable for IngredientKey {
     }
 }
 
-#[derive(Debug, Copy, Clone, Ord, PartialOrd, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Ord, PartialOrd, PartialEq, Eq, Hash)]
 pub enum IngredientPart {
     MainEffect(MainEffect),
     Element(Element),
@@ -57,7 +57,7 @@ pub enum IngredientPart {
     Antitoxin,
 }
 
-#[derive(Debug, Copy, Clone, Ord, PartialOrd, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Ord, PartialOrd, PartialEq, Eq, Hash)]
 pub enum MainEffect {
     Cat,
     Bone,
@@ -65,7 +65,7 @@ pub enum MainEffect {
     Beast,
 }
 
-#[derive(Debug, Copy, Clone, Ord, PartialOrd, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Ord, PartialOrd, PartialEq, Eq, Hash)]
 pub enum Element {
     Fire,
     Aether,
@@ -73,19 +73,19 @@ pub enum Element {
     Earth,
 }
 
-#[derive(Debug, Copy, Clone, Ord, PartialOrd, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Ord, PartialOrd, PartialEq, Eq, Hash)]
 pub enum Taste {
     Tastiness(Tastiness),
     Sweetness(Sweetness),
 }
 
-#[derive(Debug, Copy, Clone, Ord, PartialOrd, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Ord, PartialOrd, PartialEq, Eq, Hash)]
 pub enum Tastiness {
     Tasty,
     Unsavory,
 }
 
-#[derive(Debug, Copy, Clone, Ord, PartialOrd, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Ord, PartialOrd, PartialEq, Eq, Hash)]
 pub enum Sweetness {
     Bitter,
     Sweet,
@@ -150,7 +150,7 @@ impl ToHumanReadable for IngredientPart {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Ord, PartialOrd, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Ord, PartialOrd, Eq, Hash)]
 pub enum IngredientKind {
     Herb,
     Mushroom,
@@ -166,7 +166,7 @@ impl ToHumanReadable for IngredientKind {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Ingredient {
     pub key: IngredientKey,
     pub process: IngredientProcess,
@@ -180,7 +180,7 @@ impl ToHumanReadable for Ingredient {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Ord, PartialOrd, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Ord, PartialOrd, Eq, Hash)]
 pub enum IngredientParts {
     // Single processes
     Raw(
@@ -233,6 +233,47 @@ pub enum IngredientParts {
     PickledFermentedInfused(IngredientPart, IngredientPart),
 }
 
+impl IngredientParts {
+    /// Parts of an ingredient processed by the chain, or None if there are
+    /// not as many parts as the chain keeps.
+    pub fn from_process(process: &IngredientProcess, parts: &[IngredientPart]) -> Option<Self> {
+        use IngredientProcess as P;
+
+        if let [a, b, c, d] = *parts {
+            return match process {
+                P::Raw => Some(IngredientParts::Raw(a, b, c, d)),
+                P::Fermented => Some(IngredientParts::Fermented(a, b, c, d)),
+                P::Infused => Some(IngredientParts::Infused(a, b, c, d)),
+                P::FermentedInfused => Some(IngredientParts::FermentedInfused(a, b, c, d)),
+                _ => None,
+            };
+        }
+
+        let [a, b] = *parts else {
+            return None;
+        };
+        match process {
+            P::Crushed => Some(IngredientParts::Crushed(a, b)),
+            P::Blanched => Some(IngredientParts::Blanched(a, b)),
+            P::Dried => Some(IngredientParts::Dried(a, b)),
+            P::Pickled => Some(IngredientParts::Pickled(a, b)),
+            P::CrushedFermented => Some(IngredientParts::CrushedFermented(a, b)),
+            P::BlanchedFermented => Some(IngredientParts::BlanchedFermented(a, b)),
+            P::DriedFermented => Some(IngredientParts::DriedFermented(a, b)),
+            P::PickledFermented => Some(IngredientParts::PickledFermented(a, b)),
+            P::CrushedInfused => Some(IngredientParts::CrushedInfused(a, b)),
+            P::BlanchedInfused => Some(IngredientParts::BlanchedInfused(a, b)),
+            P::DriedInfused => Some(IngredientParts::DriedInfused(a, b)),
+            P::PickledInfused => Some(IngredientParts::PickledInfused(a, b)),
+            P::CrushedFermentedInfused => Some(IngredientParts::CrushedFermentedInfused(a, b)),
+            P::BlanchedFermentedInfused => Some(IngredientParts::BlanchedFermentedInfused(a, b)),
+            P::DriedFermentedInfused => Some(IngredientParts::DriedFermentedInfused(a, b)),
+            P::PickledFermentedInfused => Some(IngredientParts::PickledFermentedInfused(a, b)),
+            _ => None,
+        }
+    }
+}
+
 impl ToHumanReadable for IngredientParts {
     fn to_human(&self) -> String {
         let convert_full =
@@ -280,7 +321,7 @@ impl ToHumanReadable for IngredientParts {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
 pub enum IngredientProcess {
     // Single Processes
     Raw,
@@ -1,11 +1,11 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use super::{
-    ingredients::{Element, Ingredient, MainEffect},
+    ingredients::{Element, Ingredient, IngredientProcess, MainEffect},
     traits::{GetByKey, GetName},
 };
 
-#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
 pub enum Department {
     Health,
     Sourcery,
@@ -22,7 +22,7 @@ impl GetName for Department {
     }
 }
 
-#[derive(Deserialize, Debug, Clone, Ord, PartialOrd, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, Ord, PartialOrd, PartialEq, Eq, Hash)]
 pub enum PotionKindKey {
     Speed,
     Slow,
@@ -55,13 +55,13 @@ pub enum TasteEffect {
     TastyNegative,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum OverallPurity {
     Neutral,
     Impure,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum OverallToxicity {
     VeryToxic,
     Toxic,
@@ -70,7 +70,7 @@ pub enum OverallToxicity {
     Veryantitoxic,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum OverallTaste {
     Tasty,
     Flavorful,
@@ -178,15 +178,59 @@ impl GetName for PotionKind {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// The properties of a recipe that depend only on its ingredients.
+///
+/// Appeal and potency from the market, branding and alchemists are added
+/// when the recipe is scored against a `SimulateConfig`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RecipeIntrinsics {
+    pub potion_kind_key: PotionKindKey,
+    pub ingredients: Vec<Ingredient>,
+    /// Every process chain that yields the same parts, for each ingredient.
+    pub alternatives: Vec<Vec<IngredientProcess>>,
+    pub overall_taste: OverallTaste,
+    pub overall_toxicity: OverallToxicity,
+    pub overall_purity: OverallPurity,
+    /// Appeal from purity, taste and toxicity.
+    pub base_appeal: i32,
+    /// Potency from purity, toxicity, element and main effect.
+    pub base_potency: i32,
+    pub stimulant_count: i32,
+    pub has_herb: bool,
+    pub has_mushroom: bool,
+}
+
+/// How a potion is priced in the shop. Bulk pricing earns the Bulk branding
+/// appeal in exchange for a lower price.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Pricing {
+    Standard,
+    Bulk,
+}
+
+impl GetName for Pricing {
+    fn name(&self) -> &'static str {
+        match self {
+            Pricing::Standard => "Standard",
+            Pricing::Bulk => "Bulk",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Recipe {
     pub potion_kind_key: PotionKindKey,
     pub ingredients: Vec<Ingredient>,
+    /// Every process chain that yields the same parts, for each ingredient.
+    pub alternatives: Vec<Vec<IngredientProcess>>,
     pub overall_taste: OverallTaste,
     pub overall_toxicity: OverallToxicity,
     pub overall_purity: OverallPurity,
     pub overall_appeal: i32,
     pub overall_potency: i32,
+    pub pricing: Pricing,
+    /// Sale price of the potion, if the recipe was scored with a price model.
+    pub revenue: Option<i32>,
 }
 
 pub static POTION_KINDS: [(PotionKindKey, PotionKind); 16] = [
@@ -352,14 +396,28 @@ pub static POTION_KINDS: [(PotionKindKey, PotionKind); 16] = [
     ),
 ];
 
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+/// Attributes of an alchemist. The effect of each is applied by
+/// `determine_alchemist_appeal` and `determine_alchemist_potency`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub enum AlchemistAttribute {
     Acclaimed,
+    /// Has no effect until its rule in the game is known.
+    Enchanter,
     FungiConnoisseur,
     Herbalist,
     Optimiser,
 }
 
+/// An alchemist of the shop, whose attributes only apply to recipes of the
+/// departments they are assigned to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct Alchemist {
+    pub name: String,
+    pub attributes: Vec<AlchemistAttribute>,
+    pub departments: Vec<Department>,
+}
+
 #[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub enum MarketCondition {
     HighDemand,
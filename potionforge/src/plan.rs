@@ -0,0 +1,259 @@
+use std::collections::HashSet;
+
+use highs::{Col, HighsModelStatus, RowProblem, Sense};
+
+use crate::{
+    core::{prune_dominated, score_recipes},
+    enumerate::CandidateFilter,
+    models::{IngredientKey, Recipe, RecipeIntrinsics},
+    recommend::{
+        create_department_constraints, create_potion_kind_constraints, ingredient_uses,
+        nearly_equal, IngredientCounts, MarketConditions,
+    },
+    simulate::SimulateConfig,
+};
+
+/// Expected conditions on one day of the planning horizon.
+#[derive(Debug, Clone, Default)]
+pub struct PlanDay {
+    pub market_conditions: MarketConditions,
+    /// Ingredients arriving at the start of the day.
+    pub restock: IngredientCounts,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlanConfig {
+    /// Inventory at the start of the first day.
+    pub available_ingredients: IngredientCounts,
+    pub utilisation: i32,
+    pub days: Vec<PlanDay>,
+}
+
+/// Plan a line-up of recipes for each day, maximising the total appeal over
+/// the horizon and then the total potency (or revenue when recipes are
+/// priced).
+///
+/// Each day has the same potion kind and department limits as a single
+/// recommendation, except that a department may be left empty when stock
+/// runs out. Ingredients used on a day are no longer available on later days.
+pub fn plan(
+    intrinsics: &[RecipeIntrinsics],
+    simulate_config: &SimulateConfig,
+    filters: &[CandidateFilter],
+    config: &PlanConfig,
+) -> Vec<Vec<Recipe>> {
+    let candidates: Vec<Vec<Recipe>> = config
+        .days
+        .iter()
+        .map(|day| {
+            let day_config = SimulateConfig {
+                market_conditions: day.market_conditions.clone(),
+                ..simulate_config.clone()
+            };
+            prune_dominated(score_recipes(intrinsics, &day_config, filters)).0
+        })
+        .collect();
+
+    if candidates.iter().all(|recipes| recipes.is_empty()) {
+        return candidates;
+    }
+
+    let appeal = |recipe: &Recipe| recipe.overall_appeal as f64;
+    let value = |recipe: &Recipe| recipe.revenue.unwrap_or(recipe.overall_potency) as f64;
+
+    let (total_appeal, _) = solve(&candidates, config, appeal, None);
+    let (_, lineups) = solve(&candidates, config, value, Some(total_appeal));
+    lineups
+}
+
+/// Maximise the objective over every day, returning its optimum and the
+/// selected recipes of each day.
+fn solve<F>(
+    candidates: &[Vec<Recipe>],
+    config: &PlanConfig,
+    objective: F,
+    min_appeal: Option<f64>,
+) -> (f64, Vec<Vec<Recipe>>)
+where
+    F: Fn(&Recipe) -> f64,
+{
+    let mut pb = RowProblem::default();
+
+    // The columns: a binary variable for each recipe on each day.
+    let columns: Vec<Vec<Col>> = candidates
+        .iter()
+        .map(|recipes| {
+            recipes
+                .iter()
+                .map(|recipe| pb.add_integer_column(objective(recipe), 0..1))
+                .collect()
+        })
+        .collect();
+
+    // The rows: constraints.
+    for (day_columns, recipes) in columns.iter().zip(candidates) {
+        create_potion_kind_constraints(&mut pb, day_columns, recipes, &[]);
+        create_department_constraints(&mut pb, day_columns, recipes, 0.);
+    }
+    create_inventory_constraints(&mut pb, &columns, candidates, config);
+
+    if let Some(min_appeal) = min_appeal {
+        let factors: Vec<(Col, f64)> = columns
+            .iter()
+            .flatten()
+            .zip(candidates.iter().flatten())
+            .map(|(&column, recipe)| (column, recipe.overall_appeal as f64))
+            .collect();
+        pb.add_row(min_appeal.., factors);
+    }
+
+    let solved = pb.optimise(Sense::Maximise).solve();
+
+    // Check the solver finished and solution is proven optimal.
+    assert_eq!(solved.status(), HighsModelStatus::Optimal);
+
+    let solution = solved.get_solution();
+    let mut values = solution.columns().iter();
+
+    let mut total = 0.;
+    let lineups = candidates
+        .iter()
+        .map(|recipes| {
+            recipes
+                .iter()
+                .filter(|_| nearly_equal(*values.next().unwrap(), 1., 1e-6))
+                .inspect(|recipe| total += objective(recipe))
+                .cloned()
+                .collect()
+        })
+        .collect();
+
+    (total, lineups)
+}
+
+/// Ingredients used up to and including each day must not exceed the initial
+/// inventory plus the restocks that have arrived by then.
+fn create_inventory_constraints(
+    pb: &mut RowProblem,
+    columns: &[Vec<Col>],
+    candidates: &[Vec<Recipe>],
+    config: &PlanConfig,
+) {
+    let keys: HashSet<IngredientKey> = candidates
+        .iter()
+        .flatten()
+        .flat_map(|recipe| recipe.ingredients.iter().map(|ingredient| ingredient.key))
+        .collect();
+
+    for key in keys {
+        let mut stock = *config.available_ingredients.get(&key).unwrap_or(&0);
+        let mut factors: Vec<(Col, f64)> = Vec::new();
+
+        for ((day, day_columns), recipes) in config.days.iter().zip(columns).zip(candidates) {
+            stock += day.restock.get(&key).unwrap_or(&0);
+            factors.extend(
+                day_columns
+                    .iter()
+                    .zip(recipes)
+                    .map(|(&column, recipe)| (column, ingredient_uses(recipe, &key) as f64))
+                    .filter(|(_, uses)| *uses > 0.),
+            );
+
+            pb.add_row(0..stock * config.utilisation, factors.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        core::enumerate,
+        enumerate::EnumerateConfig,
+        models::{MarketCondition, PotionKindKey, Process},
+        progress::{CancellationToken, NoProgress},
+        testdata::enumerate_config,
+    };
+
+    use super::*;
+
+    fn config() -> (EnumerateConfig, SimulateConfig) {
+        let enumerate_config = EnumerateConfig {
+            utilisation: 1,
+            ..enumerate_config(
+                &[
+                    IngredientKey::Pluteus,
+                    IngredientKey::Thyme,
+                    IngredientKey::Sage,
+                    IngredientKey::Catnip,
+                ],
+                2,
+                vec![Process::Crush, Process::Dry],
+            )
+        };
+        let simulate_config = SimulateConfig::default();
+        (enumerate_config, simulate_config)
+    }
+
+    #[test]
+    fn test_plan_consumes_inventory() {
+        let (enumerate_config, simulate_config) = config();
+        let intrinsics =
+            enumerate(&enumerate_config, &NoProgress, &CancellationToken::new()).unwrap();
+        let available_ingredients: IngredientCounts = enumerate_config
+            .ingredients
+            .iter()
+            .map(|&key| (key, 1))
+            .collect();
+
+        let mut market_conditions = MarketConditions::new();
+        market_conditions.insert(PotionKindKey::Mana, vec![MarketCondition::HighDemand]);
+        let day = PlanDay {
+            market_conditions,
+            restock: IngredientCounts::new(),
+        };
+        let plan_config = PlanConfig {
+            available_ingredients: available_ingredients.clone(),
+            utilisation: 1,
+            days: vec![day.clone(), day.clone()],
+        };
+
+        let lineups = plan(
+            &intrinsics,
+            &simulate_config,
+            &enumerate_config.filters,
+            &plan_config,
+        );
+        assert_eq!(lineups.len(), 2);
+
+        // Each ingredient is used at most once over both days.
+        let mut used = IngredientCounts::new();
+        for recipe in lineups.iter().flatten() {
+            for ingredient in &recipe.ingredients {
+                *used.entry(ingredient.key).or_insert(0) += 1;
+            }
+        }
+        assert!(used.values().all(|&count| count <= 1));
+
+        // A restock on the second day lets it brew as much as the first.
+        let restocked = PlanConfig {
+            days: vec![
+                day.clone(),
+                PlanDay {
+                    restock: available_ingredients,
+                    ..day
+                },
+            ],
+            ..plan_config
+        };
+        let lineups = plan(
+            &intrinsics,
+            &simulate_config,
+            &enumerate_config.filters,
+            &restocked,
+        );
+        let appeal = |recipes: &Vec<Recipe>| -> i32 {
+            recipes.iter().map(|recipe| recipe.overall_appeal).sum()
+        };
+        assert_eq!(appeal(&lineups[0]), appeal(&lineups[1]));
+    }
+}
@@ -1,364 +1,788 @@
-use crate::models::{
-    Element, Ingredient, IngredientPart, IngredientParts, IngredientProcess, Process,
+use std::{error::Error, fs, path::Path, sync::Arc};
+
+use serde::Deserialize;
+
+use crate::{
+    models::{
+        Ingredient, IngredientKey, IngredientPart, IngredientParts, IngredientProcess, Process,
+        INGREDIENTS,
+    },
+    simulate::collect_parts,
 };
 
-pub fn process_ingredient(ingredient: &Ingredient, processes: &Vec<Process>) -> Ingredient {
-    let mut ingredient = ingredient.clone();
+pub const ALL_PROCESSES: [Process; 6] = [
+    Process::Crush,
+    Process::Blanch,
+    Process::Dry,
+    Process::Pickle,
+    Process::Ferment,
+    Process::Infuse,
+];
+
+/// Parts a processed ingredient must, or must not, contain.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PartQuery {
+    /// Each part listed must appear at least as often as it is listed.
+    pub include: Vec<IngredientPart>,
+    pub exclude: Vec<IngredientPart>,
+}
 
-    if processes.contains(&Process::Crush) {
-        if let Some(crushed_ingredient) = process_crush(&ingredient) {
-            ingredient = crushed_ingredient;
+impl PartQuery {
+    pub fn matches(&self, ingredient: &Ingredient) -> bool {
+        let parts = collect_parts(std::slice::from_ref(ingredient));
+        let count = |part: &IngredientPart, parts: &[IngredientPart]| {
+            parts.iter().filter(|other| *other == part).count()
         };
+
+        self.include
+            .iter()
+            .all(|part| count(part, &parts) >= count(part, &self.include))
+            && !self.exclude.iter().any(|part| parts.contains(part))
     }
+}
+
+/// Every process chain of the ingredient whose parts match the query.
+pub fn find_process_chains(
+    variants: &VariantTable,
+    key: &IngredientKey,
+    query: &PartQuery,
+) -> Vec<IngredientProcess> {
+    variants
+        .variants(key)
+        .filter(|ingredient| query.matches(ingredient))
+        .map(|ingredient| ingredient.process.clone())
+        .collect()
+}
+
+/// Every ingredient of the catalogue, under every process chain, whose parts
+/// match the query.
+pub fn find_ingredients(variants: &VariantTable, query: &PartQuery) -> Vec<Ingredient> {
+    variants
+        .iter()
+        .filter(|ingredient| query.matches(ingredient))
+        .cloned()
+        .collect()
+}
+
+/// Source of the rules of the current game release, used unless other rules
+/// are loaded.
+pub const PROCESS_RULES_SOURCE: &str = include_str!("models/process_rules.yml");
+
+lazy_static::lazy_static! {
+    /// The rules of the current game release.
+    pub static ref PROCESS_RULES: ProcessRules =
+        ProcessRules::from_yaml(PROCESS_RULES_SOURCE).unwrap();
+    /// Every variant of the catalogue under the current rules.
+    pub static ref PROCESS_VARIANTS: Arc<VariantTable> =
+        Arc::new(VariantTable::from_rules(&PROCESS_RULES));
+}
+
+/// Number of `IngredientProcess` chains.
+const PROCESS_CHAINS: usize = 20;
+
+/// A processed ingredient and the processes applied to reach it.
+#[derive(Debug, Clone)]
+struct Variant {
+    ingredient: Ingredient,
+    steps: Vec<Process>,
+    /// Position of the first variant of the ingredient with the same parts.
+    class: usize,
+}
 
-    if processes.contains(&Process::Blanch) {
-        if let Some(blanched_ingredient) = process_blanch(&ingredient) {
-            ingredient = blanched_ingredient;
+/// Every processed variant of every ingredient of the catalogue, looked up
+/// by key and process chain.
+#[derive(Debug, Clone)]
+pub struct VariantTable {
+    /// Variants of each ingredient, by key, in the order `permute_ingredient`
+    /// lists them.
+    variants: Vec<Vec<Variant>>,
+    /// Position among the variants of each key and process chain.
+    index: Vec<[Option<usize>; PROCESS_CHAINS]>,
+    /// Whether every variant of each ingredient has its own parts.
+    distinct: bool,
+    rules: ProcessRules,
+}
+
+impl VariantTable {
+    pub fn from_rules(rules: &ProcessRules) -> Self {
+        let mut variants = vec![Vec::new(); INGREDIENTS.0.len()];
+        let mut index = vec![[None; PROCESS_CHAINS]; INGREDIENTS.0.len()];
+        let mut distinct = true;
+
+        for (key, raw_ingredient) in INGREDIENTS.0.iter() {
+            let mut chains = vec![Variant {
+                ingredient: raw_ingredient.clone(),
+                steps: vec![],
+                class: 0,
+            }];
+            for process in ALL_PROCESSES.iter() {
+                for variant in chains.clone() {
+                    if let Some(ingredient) = rules.apply(&variant.ingredient, process) {
+                        let mut steps = variant.steps;
+                        steps.push(*process);
+                        chains.push(Variant {
+                            ingredient,
+                            steps,
+                            class: 0,
+                        });
+                    }
+                }
+            }
+
+            let parts: Vec<Vec<IngredientPart>> = chains
+                .iter()
+                .map(|variant| {
+                    let mut parts = collect_parts(std::slice::from_ref(&variant.ingredient));
+                    parts.sort();
+                    parts
+                })
+                .collect();
+            for (position, variant) in chains.iter_mut().enumerate() {
+                index[*key as usize][variant.ingredient.process.clone() as usize] = Some(position);
+                variant.class = parts
+                    .iter()
+                    .position(|other| *other == parts[position])
+                    .unwrap();
+                distinct &= variant.class == position;
+            }
+            variants[*key as usize] = chains;
         }
-    }
 
-    if processes.contains(&Process::Dry) {
-        if let Some(dried_ingredient) = process_dry(&ingredient) {
-            ingredient = dried_ingredient;
+        VariantTable {
+            variants,
+            index,
+            distinct,
+            rules: rules.clone(),
         }
     }
 
-    if processes.contains(&Process::Pickle) {
-        if let Some(pickled_ingredient) = process_pickle(&ingredient) {
-            ingredient = pickled_ingredient;
+    /// The rules the variants were processed with.
+    pub fn rules(&self) -> &ProcessRules {
+        &self.rules
+    }
+
+    /// The ingredient under the process chain, or None if the chain cannot
+    /// be applied to it.
+    pub fn get(&self, key: &IngredientKey, process: &IngredientProcess) -> Option<&Ingredient> {
+        let position = self.index[*key as usize][process.clone() as usize]?;
+        Some(&self.variants[*key as usize][position].ingredient)
+    }
+
+    /// The processes applied to reach the process chain, or None if the chain
+    /// cannot be applied to the ingredient.
+    pub fn steps(&self, key: &IngredientKey, process: &IngredientProcess) -> Option<&[Process]> {
+        let position = self.index[*key as usize][process.clone() as usize]?;
+        Some(&self.variants[*key as usize][position].steps)
+    }
+
+    /// Whether no two process chains of an ingredient give the same parts, so
+    /// every variant is its own equivalence class.
+    pub fn is_distinct(&self) -> bool {
+        self.distinct
+    }
+
+    /// Position, among the variants of the ingredient, of the first variant
+    /// with the same parts as the process chain, or None if the chain cannot
+    /// be applied to it. Chains with the same class are interchangeable.
+    pub fn class(&self, key: &IngredientKey, process: &IngredientProcess) -> Option<usize> {
+        let position = self.index[*key as usize][process.clone() as usize]?;
+        Some(self.variants[*key as usize][position].class)
+    }
+
+    /// Every variant of the ingredient, raw first.
+    pub fn variants(&self, key: &IngredientKey) -> impl Iterator<Item = &Ingredient> {
+        self.variants[*key as usize]
+            .iter()
+            .map(|variant| &variant.ingredient)
+    }
+
+    /// Every variant of every ingredient, in catalogue order.
+    pub fn iter(&self) -> impl Iterator<Item = &Ingredient> {
+        INGREDIENTS.0.iter().flat_map(|(key, _)| self.variants(key))
+    }
+
+    /// The variants of the ingredient reachable with the processes, as
+    /// `permute_ingredient` lists them for the raw ingredient.
+    pub fn permutations<'a>(
+        &'a self,
+        key: &IngredientKey,
+        processes: &'a [Process],
+    ) -> impl Iterator<Item = &'a Ingredient> {
+        self.variants[*key as usize]
+            .iter()
+            .filter(move |variant| variant.steps.iter().all(|step| processes.contains(step)))
+            .map(|variant| &variant.ingredient)
+    }
+
+    /// The raw ingredient with the processes applied, as `process_ingredient`
+    /// applies them.
+    pub fn process(&self, key: &IngredientKey, processes: &[Process]) -> &Ingredient {
+        let mut chain = IngredientProcess::Raw;
+        for process in ALL_PROCESSES.iter() {
+            if !processes.contains(process) {
+                continue;
+            }
+            let next = match process {
+                Process::Ferment => fermented_process(&chain),
+                Process::Infuse => infused_process(&chain),
+                _ if chain == IngredientProcess::Raw => cut_process(process),
+                _ => None,
+            };
+            if let Some(next) = next.filter(|next| self.get(key, next).is_some()) {
+                chain = next;
+            }
         }
+        // Raw is always present.
+        self.get(key, &chain).unwrap()
+    }
+}
+
+/// Parts of a raw ingredient kept by a cutting process.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CutRule {
+    pub process: Process,
+    /// Positions among the four raw parts, in the order they are kept.
+    pub kept: Vec<usize>,
+}
+
+/// A part and what a process turns it into.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PartRule {
+    pub from: IngredientPart,
+    pub to: IngredientPart,
+}
+
+/// What each process does to the parts of an ingredient.
+///
+/// Which chains of processes are possible is fixed: only raw ingredients can
+/// be cut, and fermenting must come before infusing.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProcessRules {
+    pub cuts: Vec<CutRule>,
+    /// Parts replaced by fermenting, which needs at least one of them.
+    pub ferment: Vec<PartRule>,
+    /// Parts swapped by infusing, which needs at least one of them.
+    pub infuse: Vec<PartRule>,
+}
+
+impl ProcessRules {
+    pub fn from_yaml(yaml: &str) -> Result<Self, serde_yaml::Error> {
+        serde_yaml::from_str(yaml)
+    }
+
+    /// Read rules written like `models/process_rules.yml`, such as those of
+    /// a newer game release.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        Self::from_yaml(&contents).map_err(|error| format!("{}: {}", path.display(), error).into())
+    }
+
+    /// Apply a single process, or None if the ingredient cannot take it.
+    pub fn apply(&self, ingredient: &Ingredient, process: &Process) -> Option<Ingredient> {
+        let parts = collect_parts(std::slice::from_ref(ingredient));
+
+        let (new_process, new_parts) = match process {
+            Process::Ferment => (
+                fermented_process(&ingredient.process)?,
+                replace_parts(&parts, &self.ferment)?,
+            ),
+            Process::Infuse => (
+                infused_process(&ingredient.process)?,
+                replace_parts(&parts, &self.infuse)?,
+            ),
+            _ => {
+                if ingredient.process != IngredientProcess::Raw {
+                    return None;
+                }
+                let rule = self.cuts.iter().find(|rule| rule.process == *process)?;
+                let kept = rule
+                    .kept
+                    .iter()
+                    .map(|&position| parts.get(position).copied())
+                    .collect::<Option<Vec<_>>>()?;
+                (cut_process(process)?, kept)
+            }
+        };
+
+        Some(Ingredient {
+            key: ingredient.key,
+            parts: IngredientParts::from_process(&new_process, &new_parts)?,
+            process: new_process,
+            kind: ingredient.kind.clone(),
+        })
     }
 
-    if processes.contains(&Process::Ferment) {
-        if let Some(ferment_result) = process_ferment(&ingredient) {
-            ingredient = ferment_result;
+    /// Apply the processes in the same order as `process_ingredient`,
+    /// recording each step.
+    pub fn trace(&self, ingredient: &Ingredient, processes: &[Process]) -> ProcessTrace {
+        let mut ingredient = ingredient.clone();
+        let mut steps = Vec::new();
+
+        for process in ALL_PROCESSES.iter() {
+            if !processes.contains(process) {
+                continue;
+            }
+
+            let before = collect_parts(std::slice::from_ref(&ingredient));
+            let processed = self.apply(&ingredient, process);
+
+            let outcome = match &processed {
+                Some(processed) => {
+                    let after = collect_parts(std::slice::from_ref(processed));
+                    match process {
+                        Process::Ferment => StepOutcome::Fermented {
+                            replaced: changed_parts(&before, &after),
+                        },
+                        Process::Infuse => StepOutcome::Infused {
+                            swapped: changed_parts(&before, &after),
+                        },
+                        _ => {
+                            let mut dropped = before.clone();
+                            for part in &after {
+                                if let Some(position) =
+                                    dropped.iter().position(|other| other == part)
+                                {
+                                    dropped.remove(position);
+                                }
+                            }
+                            StepOutcome::Cut { dropped }
+                        }
+                    }
+                }
+                None => StepOutcome::Skipped(self.skip_reason(&ingredient, process, &before)),
+            };
+
+            if let Some(processed) = processed {
+                ingredient = processed;
+            }
+            steps.push(ProcessStep {
+                process: *process,
+                after: collect_parts(std::slice::from_ref(&ingredient)),
+                before,
+                outcome,
+            });
+        }
+
+        ProcessTrace {
+            steps,
+            result: ingredient,
         }
     }
 
-    if processes.contains(&Process::Infuse) {
-        if let Some(infuse_result) = process_infuse(&ingredient) {
-            ingredient = infuse_result;
+    fn skip_reason(
+        &self,
+        ingredient: &Ingredient,
+        process: &Process,
+        parts: &[IngredientPart],
+    ) -> SkipReason {
+        let affects = |rules: &[PartRule]| {
+            parts
+                .iter()
+                .any(|part| rules.iter().any(|rule| rule.from == *part))
+        };
+
+        match process {
+            Process::Ferment if is_fermented(&ingredient.process) => SkipReason::AlreadyFermented,
+            Process::Ferment if fermented_process(&ingredient.process).is_none() => {
+                SkipReason::AlreadyInfused
+            }
+            Process::Ferment => SkipReason::NoImpurity,
+            Process::Infuse if !affects(&self.infuse) => SkipReason::NoElement,
+            Process::Infuse => SkipReason::AlreadyInfused,
+            _ => SkipReason::AlreadyCut,
         }
     }
+}
 
-    ingredient
+pub fn process_ingredient(ingredient: &Ingredient, processes: &Vec<Process>) -> Ingredient {
+    trace_process_ingredient(ingredient, processes).result
 }
 
-pub fn process_crush(ingredient: &Ingredient) -> Option<Ingredient> {
-    match &ingredient.parts {
-        IngredientParts::Raw(_, b, c, _) => Some(Ingredient {
-            key: ingredient.key.clone(),
-            process: IngredientProcess::Crushed,
-            kind: ingredient.kind.clone(),
-            parts: IngredientParts::Crushed(b.clone(), c.clone()),
-        }),
-        _ => None,
+/// Why a requested process left the ingredient unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Only raw ingredients can be cut.
+    AlreadyCut,
+    NoImpurity,
+    AlreadyFermented,
+    /// Fermenting must come before infusing.
+    AlreadyInfused,
+    NoElement,
+}
+
+/// What a single process did to the parts of an ingredient.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// Cutting keeps two of the four parts.
+    Cut {
+        dropped: Vec<IngredientPart>,
+    },
+    /// Each impurity replaced, with what replaced it.
+    Fermented {
+        replaced: Vec<(IngredientPart, IngredientPart)>,
+    },
+    /// Each element swapped, with what it became.
+    Infused {
+        swapped: Vec<(IngredientPart, IngredientPart)>,
+    },
+    Skipped(SkipReason),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessStep {
+    pub process: Process,
+    pub before: Vec<IngredientPart>,
+    pub after: Vec<IngredientPart>,
+    pub outcome: StepOutcome,
+}
+
+/// Every requested process applied to an ingredient, in the order
+/// `process_ingredient` applies them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessTrace {
+    pub steps: Vec<ProcessStep>,
+    pub result: Ingredient,
+}
+
+/// Apply the processes like `process_ingredient`, recording each step.
+pub fn trace_process_ingredient(ingredient: &Ingredient, processes: &[Process]) -> ProcessTrace {
+    PROCESS_RULES.trace(ingredient, processes)
+}
+
+/// Parts changed in place by fermenting or infusing, with their replacements.
+fn changed_parts(
+    before: &[IngredientPart],
+    after: &[IngredientPart],
+) -> Vec<(IngredientPart, IngredientPart)> {
+    before
+        .iter()
+        .zip(after)
+        .filter(|(before, after)| before != after)
+        .map(|(&before, &after)| (before, after))
+        .collect()
+}
+
+/// Replace every part a rule applies to, or None if no rule applies.
+fn replace_parts(parts: &[IngredientPart], rules: &[PartRule]) -> Option<Vec<IngredientPart>> {
+    let mut replaced = false;
+    let new_parts = parts
+        .iter()
+        .map(|part| match rules.iter().find(|rule| rule.from == *part) {
+            Some(rule) => {
+                replaced = true;
+                rule.to
+            }
+            None => *part,
+        })
+        .collect();
+    if replaced {
+        Some(new_parts)
+    } else {
+        None
     }
 }
 
-pub fn process_blanch(ingredient: &Ingredient) -> Option<Ingredient> {
-    match &ingredient.parts {
-        IngredientParts::Raw(a, b, _, _) => Some(Ingredient {
-            key: ingredient.key.clone(),
-            process: IngredientProcess::Blanched,
-            kind: ingredient.kind.clone(),
-            parts: IngredientParts::Blanched(a.clone(), b.clone()),
-        }),
-        _ => None,
+fn cut_process(process: &Process) -> Option<IngredientProcess> {
+    match process {
+        Process::Crush => Some(IngredientProcess::Crushed),
+        Process::Blanch => Some(IngredientProcess::Blanched),
+        Process::Dry => Some(IngredientProcess::Dried),
+        Process::Pickle => Some(IngredientProcess::Pickled),
+        Process::Ferment | Process::Infuse => None,
     }
 }
 
-pub fn process_dry(ingredient: &Ingredient) -> Option<Ingredient> {
-    match &ingredient.parts {
-        IngredientParts::Raw(a, _, _, d) => Some(Ingredient {
-            key: ingredient.key.clone(),
-            process: IngredientProcess::Dried,
-            kind: ingredient.kind.clone(),
-            parts: IngredientParts::Dried(a.clone(), d.clone()),
-        }),
+fn is_fermented(process: &IngredientProcess) -> bool {
+    matches!(
+        process,
+        IngredientProcess::Fermented
+            | IngredientProcess::CrushedFermented
+            | IngredientProcess::BlanchedFermented
+            | IngredientProcess::DriedFermented
+            | IngredientProcess::PickledFermented
+            | IngredientProcess::FermentedInfused
+            | IngredientProcess::CrushedFermentedInfused
+            | IngredientProcess::BlanchedFermentedInfused
+            | IngredientProcess::DriedFermentedInfused
+            | IngredientProcess::PickledFermentedInfused
+    )
+}
+
+/// The chain after fermenting, or None if it is already fermented or infused.
+fn fermented_process(process: &IngredientProcess) -> Option<IngredientProcess> {
+    match process {
+        IngredientProcess::Raw => Some(IngredientProcess::Fermented),
+        IngredientProcess::Crushed => Some(IngredientProcess::CrushedFermented),
+        IngredientProcess::Blanched => Some(IngredientProcess::BlanchedFermented),
+        IngredientProcess::Dried => Some(IngredientProcess::DriedFermented),
+        IngredientProcess::Pickled => Some(IngredientProcess::PickledFermented),
         _ => None,
     }
 }
 
-pub fn process_pickle(ingredient: &Ingredient) -> Option<Ingredient> {
-    match &ingredient.parts {
-        IngredientParts::Raw(_, _, c, d) => Some(Ingredient {
-            key: ingredient.key.clone(),
-            process: IngredientProcess::Pickled,
-            kind: ingredient.kind.clone(),
-            parts: IngredientParts::Pickled(c.clone(), d.clone()),
-        }),
+/// The chain after infusing, or None if it is already infused.
+fn infused_process(process: &IngredientProcess) -> Option<IngredientProcess> {
+    match process {
+        IngredientProcess::Raw => Some(IngredientProcess::Infused),
+        IngredientProcess::Crushed => Some(IngredientProcess::CrushedInfused),
+        IngredientProcess::Blanched => Some(IngredientProcess::BlanchedInfused),
+        IngredientProcess::Dried => Some(IngredientProcess::DriedInfused),
+        IngredientProcess::Pickled => Some(IngredientProcess::PickledInfused),
+        IngredientProcess::Fermented => Some(IngredientProcess::FermentedInfused),
+        IngredientProcess::CrushedFermented => Some(IngredientProcess::CrushedFermentedInfused),
+        IngredientProcess::BlanchedFermented => Some(IngredientProcess::BlanchedFermentedInfused),
+        IngredientProcess::DriedFermented => Some(IngredientProcess::DriedFermentedInfused),
+        IngredientProcess::PickledFermented => Some(IngredientProcess::PickledFermentedInfused),
         _ => None,
     }
 }
 
-pub fn process_ferment(ingredient: &Ingredient) -> Option<Ingredient> {
-    let has_impurity = match &ingredient.parts {
-        IngredientParts::Raw(a, b, c, d)
-        | IngredientParts::Fermented(a, b, c, d)
-        | IngredientParts::Infused(a, b, c, d)
-        | IngredientParts::FermentedInfused(a, b, c, d) => {
-            *a == IngredientPart::Impurity
-                || *b == IngredientPart::Impurity
-                || *c == IngredientPart::Impurity
-                || *d == IngredientPart::Impurity
-        }
-        IngredientParts::Crushed(a, b)
-        | IngredientParts::Blanched(a, b)
-        | IngredientParts::Dried(a, b)
-        | IngredientParts::Pickled(a, b)
-        | IngredientParts::CrushedFermented(a, b)
-        | IngredientParts::BlanchedFermented(a, b)
-        | IngredientParts::DriedFermented(a, b)
-        | IngredientParts::PickledFermented(a, b)
-        | IngredientParts::CrushedInfused(a, b)
-        | IngredientParts::BlanchedInfused(a, b)
-        | IngredientParts::DriedInfused(a, b)
-        | IngredientParts::PickledInfused(a, b)
-        | IngredientParts::CrushedFermentedInfused(a, b)
-        | IngredientParts::BlanchedFermentedInfused(a, b)
-        | IngredientParts::DriedFermentedInfused(a, b)
-        | IngredientParts::PickledFermentedInfused(a, b) => {
-            *a == IngredientPart::Impurity || *b == IngredientPart::Impurity
-        }
-    };
+pub fn process_crush(ingredient: &Ingredient) -> Option<Ingredient> {
+    PROCESS_RULES.apply(ingredient, &Process::Crush)
+}
 
-    if !has_impurity {
-        return None;
-    }
+pub fn process_blanch(ingredient: &Ingredient) -> Option<Ingredient> {
+    PROCESS_RULES.apply(ingredient, &Process::Blanch)
+}
 
-    let new_parts = match &ingredient.parts {
-        IngredientParts::Raw(a, b, c, d) => IngredientParts::Fermented(
-            if *a == IngredientPart::Impurity {
-                IngredientPart::Stimulant
-            } else {
-                a.clone()
-            },
-            if *b == IngredientPart::Impurity {
-                IngredientPart::Stimulant
-            } else {
-                b.clone()
-            },
-            if *c == IngredientPart::Impurity {
-                IngredientPart::Stimulant
-            } else {
-                c.clone()
-            },
-            if *d == IngredientPart::Impurity {
-                IngredientPart::Stimulant
-            } else {
-                d.clone()
-            },
-        ),
-        IngredientParts::Crushed(a, b) => IngredientParts::CrushedFermented(
-            if *a == IngredientPart::Impurity {
-                IngredientPart::Stimulant
-            } else {
-                a.clone()
-            },
-            if *b == IngredientPart::Impurity {
-                IngredientPart::Stimulant
-            } else {
-                b.clone()
-            },
-        ),
-        IngredientParts::Blanched(a, b) => IngredientParts::BlanchedFermented(
-            if *a == IngredientPart::Impurity {
-                IngredientPart::Stimulant
-            } else {
-                a.clone()
-            },
-            if *b == IngredientPart::Impurity {
-                IngredientPart::Stimulant
-            } else {
-                b.clone()
-            },
-        ),
-        IngredientParts::Dried(a, b) => IngredientParts::DriedFermented(
-            if *a == IngredientPart::Impurity {
-                IngredientPart::Stimulant
-            } else {
-                a.clone()
-            },
-            if *b == IngredientPart::Impurity {
-                IngredientPart::Stimulant
-            } else {
-                b.clone()
-            },
-        ),
-        IngredientParts::Pickled(a, b) => IngredientParts::PickledFermented(
-            if *a == IngredientPart::Impurity {
-                IngredientPart::Stimulant
-            } else {
-                a.clone()
-            },
-            if *b == IngredientPart::Impurity {
-                IngredientPart::Stimulant
-            } else {
-                b.clone()
-            },
-        ),
-        IngredientParts::Fermented(_, _, _, _) => return None, // Already fermented
-        IngredientParts::Infused(_, _, _, _) => return None,   // Cannot ferment after infusion
-        IngredientParts::CrushedFermented(_, _) => return None, // Already fermented
-        IngredientParts::BlanchedFermented(_, _) => return None, // Already fermented
-        IngredientParts::DriedFermented(_, _) => return None,  // Already fermented
-        IngredientParts::PickledFermented(_, _) => return None, // Already fermented
-        IngredientParts::CrushedInfused(_, _) => return None,  // Cannot ferment after infusion
-        IngredientParts::BlanchedInfused(_, _) => return None, // Cannot ferment after infusion
-        IngredientParts::DriedInfused(_, _) => return None,    // Cannot ferment after infusion
-        IngredientParts::PickledInfused(_, _) => return None,  // Cannot ferment after infusion
-        IngredientParts::FermentedInfused(_, _, _, _) => return None, // Already fermented
-        IngredientParts::CrushedFermentedInfused(_, _) => return None, // Already fermented
-        IngredientParts::BlanchedFermentedInfused(_, _) => return None, // Already fermented
-        IngredientParts::DriedFermentedInfused(_, _) => return None, // Already fermented
-        IngredientParts::PickledFermentedInfused(_, _) => return None, // Already fermented
-    };
+pub fn process_dry(ingredient: &Ingredient) -> Option<Ingredient> {
+    PROCESS_RULES.apply(ingredient, &Process::Dry)
+}
 
-    let new_process = match ingredient.process {
-        IngredientProcess::Raw => IngredientProcess::Fermented,
-        IngredientProcess::Crushed => IngredientProcess::CrushedFermented,
-        IngredientProcess::Blanched => IngredientProcess::BlanchedFermented,
-        IngredientProcess::Dried => IngredientProcess::DriedFermented,
-        IngredientProcess::Pickled => IngredientProcess::PickledFermented,
-        _ => return None,
-    };
+pub fn process_pickle(ingredient: &Ingredient) -> Option<Ingredient> {
+    PROCESS_RULES.apply(ingredient, &Process::Pickle)
+}
 
-    Some(Ingredient {
-        key: ingredient.key.clone(),
-        process: new_process,
-        kind: ingredient.kind.clone(),
-        parts: new_parts,
-    })
+pub fn process_ferment(ingredient: &Ingredient) -> Option<Ingredient> {
+    PROCESS_RULES.apply(ingredient, &Process::Ferment)
 }
 
 pub fn process_infuse(ingredient: &Ingredient) -> Option<Ingredient> {
-    let has_element = match &ingredient.parts {
-        IngredientParts::Raw(a, b, c, d)
-        | IngredientParts::Fermented(a, b, c, d)
-        | IngredientParts::Infused(a, b, c, d)
-        | IngredientParts::FermentedInfused(a, b, c, d) => {
-            matches!(a, IngredientPart::Element(_))
-                || matches!(b, IngredientPart::Element(_))
-                || matches!(c, IngredientPart::Element(_))
-                || matches!(d, IngredientPart::Element(_))
-        }
-        IngredientParts::Crushed(a, b)
-        | IngredientParts::Blanched(a, b)
-        | IngredientParts::Dried(a, b)
-        | IngredientParts::Pickled(a, b)
-        | IngredientParts::CrushedFermented(a, b)
-        | IngredientParts::BlanchedFermented(a, b)
-        | IngredientParts::DriedFermented(a, b)
-        | IngredientParts::PickledFermented(a, b)
-        | IngredientParts::CrushedInfused(a, b)
-        | IngredientParts::BlanchedInfused(a, b)
-        | IngredientParts::DriedInfused(a, b)
-        | IngredientParts::PickledInfused(a, b)
-        | IngredientParts::CrushedFermentedInfused(a, b)
-        | IngredientParts::BlanchedFermentedInfused(a, b)
-        | IngredientParts::DriedFermentedInfused(a, b)
-        | IngredientParts::PickledFermentedInfused(a, b) => {
-            matches!(a, IngredientPart::Element(_)) || matches!(b, IngredientPart::Element(_))
-        }
+    PROCESS_RULES.apply(ingredient, &Process::Infuse)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        enumerate::permute_ingredient,
+        models::{traits::GetByKey, Element},
     };
 
-    if !has_element {
-        return None;
+    use super::*;
+
+    /// Every subset of the processes.
+    fn process_subsets() -> impl Iterator<Item = Vec<Process>> {
+        (0..1 << ALL_PROCESSES.len()).map(|mask| {
+            ALL_PROCESSES
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| mask & (1 << i) != 0)
+                .map(|(_, process)| *process)
+                .collect()
+        })
     }
 
-    let new_parts = match &ingredient.parts {
-        IngredientParts::Raw(a, b, c, d) => IngredientParts::Infused(
-            swap_elements(a.clone()),
-            swap_elements(b.clone()),
-            swap_elements(c.clone()),
-            swap_elements(d.clone()),
-        ),
-        IngredientParts::Crushed(a, b) => {
-            IngredientParts::CrushedInfused(swap_elements(a.clone()), swap_elements(b.clone()))
+    /// Parts as the hand written processing of the first game release gave
+    /// them: the first cut keeps two fixed positions of the raw parts,
+    /// fermenting turns Impurity into Stimulant and infusing swaps Fire with
+    /// Water and Aether with Earth.
+    fn original_parts(raw: &[IngredientPart], processes: &[Process]) -> Vec<IngredientPart> {
+        let cuts = [
+            (Process::Crush, [1, 2]),
+            (Process::Blanch, [0, 1]),
+            (Process::Dry, [0, 3]),
+            (Process::Pickle, [2, 3]),
+        ];
+        let mut parts: Vec<IngredientPart> =
+            match cuts.iter().find(|(process, _)| processes.contains(process)) {
+                Some((_, kept)) => kept.iter().map(|&i| raw[i]).collect(),
+                None => raw.to_vec(),
+            };
+
+        if processes.contains(&Process::Ferment) {
+            for part in parts.iter_mut() {
+                if *part == IngredientPart::Impurity {
+                    *part = IngredientPart::Stimulant;
+                }
+            }
         }
-        IngredientParts::Blanched(a, b) => {
-            IngredientParts::BlanchedInfused(swap_elements(a.clone()), swap_elements(b.clone()))
+        if processes.contains(&Process::Infuse) {
+            for part in parts.iter_mut() {
+                *part = match *part {
+                    IngredientPart::Element(Element::Fire) => {
+                        IngredientPart::Element(Element::Water)
+                    }
+                    IngredientPart::Element(Element::Water) => {
+                        IngredientPart::Element(Element::Fire)
+                    }
+                    IngredientPart::Element(Element::Aether) => {
+                        IngredientPart::Element(Element::Earth)
+                    }
+                    IngredientPart::Element(Element::Earth) => {
+                        IngredientPart::Element(Element::Aether)
+                    }
+                    other => other,
+                };
+            }
         }
-        IngredientParts::Dried(a, b) => {
-            IngredientParts::DriedInfused(swap_elements(a.clone()), swap_elements(b.clone()))
+        parts
+    }
+
+    #[test]
+    fn test_find_process_chains() {
+        let query = PartQuery {
+            include: vec![IngredientPart::Element(Element::Water)],
+            exclude: vec![IngredientPart::Impurity],
+        };
+
+        for key in [IngredientKey::Sage, IngredientKey::Pluteus] {
+            let chains = find_process_chains(&PROCESS_VARIANTS, &key, &query);
+            for ingredient in
+                permute_ingredient(INGREDIENTS.get_by_key(&key), &ALL_PROCESSES.to_vec())
+            {
+                assert_eq!(
+                    chains.contains(&ingredient.process),
+                    query.matches(&ingredient)
+                );
+            }
         }
-        IngredientParts::Pickled(a, b) => {
-            IngredientParts::PickledInfused(swap_elements(a.clone()), swap_elements(b.clone()))
+
+        // Infusing swaps Fire for Water, so some ingredient gains it that way.
+        let found = find_ingredients(&PROCESS_VARIANTS, &query);
+        assert!(found
+            .iter()
+            .any(|ingredient| ingredient.process == IngredientProcess::Infused));
+        assert!(found.iter().all(|ingredient| query.matches(ingredient)));
+    }
+
+    #[test]
+    fn test_trace_process_ingredient() {
+        for (key, ingredient) in INGREDIENTS.0.iter() {
+            let processes = ALL_PROCESSES.to_vec();
+            let trace = trace_process_ingredient(ingredient, &processes);
+
+            assert_eq!(trace.steps.len(), ALL_PROCESSES.len());
+            assert_eq!(trace.result.key, *key);
+            assert_eq!(
+                trace.steps.last().unwrap().after,
+                collect_parts(std::slice::from_ref(&trace.result))
+            );
+            // Only the first cut applies.
+            assert!(matches!(trace.steps[0].outcome, StepOutcome::Cut { .. }));
+            for step in &trace.steps[1..4] {
+                assert_eq!(step.outcome, StepOutcome::Skipped(SkipReason::AlreadyCut));
+            }
+            for step in &trace.steps {
+                match &step.outcome {
+                    StepOutcome::Cut { dropped } => assert_eq!(dropped.len(), 2),
+                    StepOutcome::Fermented { replaced } => assert!(replaced
+                        .iter()
+                        .all(|&(from, to)| from == IngredientPart::Impurity
+                            && to == IngredientPart::Stimulant)),
+                    StepOutcome::Infused { swapped } => assert!(swapped
+                        .iter()
+                        .all(|&(from, to)| PROCESS_RULES.infuse.contains(&PartRule { from, to }))),
+                    StepOutcome::Skipped(_) => assert_eq!(step.before, step.after),
+                }
+            }
         }
-        IngredientParts::Fermented(a, b, c, d) => IngredientParts::FermentedInfused(
-            swap_elements(a.clone()),
-            swap_elements(b.clone()),
-            swap_elements(c.clone()),
-            swap_elements(d.clone()),
-        ),
-        IngredientParts::CrushedFermented(a, b) => IngredientParts::CrushedFermentedInfused(
-            swap_elements(a.clone()),
-            swap_elements(b.clone()),
-        ),
-        IngredientParts::BlanchedFermented(a, b) => IngredientParts::BlanchedFermentedInfused(
-            swap_elements(a.clone()),
-            swap_elements(b.clone()),
-        ),
-        IngredientParts::DriedFermented(a, b) => IngredientParts::DriedFermentedInfused(
-            swap_elements(a.clone()),
-            swap_elements(b.clone()),
-        ),
-
-        IngredientParts::PickledFermented(a, b) => IngredientParts::PickledFermentedInfused(
-            swap_elements(a.clone()),
-            swap_elements(b.clone()),
-        ),
-
-        IngredientParts::Infused(_, _, _, _) => return None, // Already infused
-        IngredientParts::CrushedInfused(_, _) => return None, // Already infused
-        IngredientParts::BlanchedInfused(_, _) => return None, // Already infused
-        IngredientParts::DriedInfused(_, _) => return None,  // Already infused
-        IngredientParts::PickledInfused(_, _) => return None, // Already infused
-        IngredientParts::FermentedInfused(_, _, _, _) => return None, // Already infused
-        IngredientParts::CrushedFermentedInfused(_, _) => return None, // Already infused
-        IngredientParts::BlanchedFermentedInfused(_, _) => return None, // Already infused
-        IngredientParts::DriedFermentedInfused(_, _) => return None, // Already infused
-        IngredientParts::PickledFermentedInfused(_, _) => return None, // Already infused
-    };
+    }
 
-    let new_process = match ingredient.process {
-        IngredientProcess::Raw => IngredientProcess::Infused,
-        IngredientProcess::Crushed => IngredientProcess::CrushedInfused,
-        IngredientProcess::Blanched => IngredientProcess::BlanchedInfused,
-        IngredientProcess::Dried => IngredientProcess::DriedInfused,
-        IngredientProcess::Pickled => IngredientProcess::PickledInfused,
-        IngredientProcess::Fermented => IngredientProcess::FermentedInfused,
-        IngredientProcess::CrushedFermented => IngredientProcess::CrushedFermentedInfused,
-        IngredientProcess::BlanchedFermented => IngredientProcess::BlanchedFermentedInfused,
-        IngredientProcess::DriedFermented => IngredientProcess::DriedFermentedInfused,
-        IngredientProcess::PickledFermented => IngredientProcess::PickledFermentedInfused,
-        IngredientProcess::Infused => return None, // Already infused
-        IngredientProcess::CrushedInfused => return None, // Already infused
-        IngredientProcess::BlanchedInfused => return None, // Already infused
-        IngredientProcess::DriedInfused => return None, // Already infused
-        IngredientProcess::PickledInfused => return None, // Already infused
-        IngredientProcess::FermentedInfused => return None, // Already infused
-        IngredientProcess::CrushedFermentedInfused => return None, // Already infused
-        IngredientProcess::BlanchedFermentedInfused => return None, // Already infused
-        IngredientProcess::DriedFermentedInfused => return None, // Already infused
-        IngredientProcess::PickledFermentedInfused => return None, // Already infused
-    };
+    #[test]
+    fn test_rules_from_yaml() {
+        let rules = ProcessRules::from_yaml(
+            "
+cuts:
+  - process: Crush
+    kept: [3, 0]
+ferment: []
+infuse:
+  - from: Stimulant
+    to: Impurity
+",
+        )
+        .unwrap();
+        let ingredient = INGREDIENTS.get_by_key(&IngredientKey::Thyme);
+        let parts = collect_parts(std::slice::from_ref(ingredient));
 
-    Some(Ingredient {
-        key: ingredient.key.clone(),
-        process: new_process,
-        kind: ingredient.kind.clone(),
-        parts: new_parts,
-    })
-}
+        let crushed = rules.apply(ingredient, &Process::Crush).unwrap();
+        assert_eq!(
+            collect_parts(std::slice::from_ref(&crushed)),
+            vec![parts[3], parts[0]]
+        );
+        assert_eq!(rules.apply(ingredient, &Process::Dry), None);
+        assert_eq!(rules.apply(ingredient, &Process::Ferment), None);
 
-fn swap_elements(part: IngredientPart) -> IngredientPart {
-    match part {
-        IngredientPart::Element(Element::Fire) => IngredientPart::Element(Element::Water),
-        IngredientPart::Element(Element::Water) => IngredientPart::Element(Element::Fire),
-        IngredientPart::Element(Element::Aether) => IngredientPart::Element(Element::Earth),
-        IngredientPart::Element(Element::Earth) => IngredientPart::Element(Element::Aether),
-        _ => part,
+        let trace = rules.trace(ingredient, &[Process::Infuse]);
+        assert_eq!(trace.result.process, IngredientProcess::Infused);
+        assert!(!collect_parts(std::slice::from_ref(&trace.result))
+            .contains(&IngredientPart::Stimulant));
+    }
+
+    #[test]
+    fn test_part_query_counts_parts() {
+        let ingredient = INGREDIENTS.get_by_key(&IngredientKey::Catnip);
+        let parts = collect_parts(std::slice::from_ref(ingredient));
+        let part = parts[0];
+        let times = parts.iter().filter(|other| **other == part).count();
+
+        let query = |include: Vec<IngredientPart>| PartQuery {
+            include,
+            exclude: vec![],
+        };
+        assert!(query(vec![part; times]).matches(ingredient));
+        assert!(!query(vec![part; times + 1]).matches(ingredient));
+    }
+
+    #[test]
+    fn test_variant_table_matches_processing() {
+        for (key, raw_ingredient) in INGREDIENTS.0.iter() {
+            for variant in PROCESS_VARIANTS.variants(key) {
+                assert_eq!(PROCESS_VARIANTS.get(key, &variant.process), Some(variant));
+            }
+
+            for processes in process_subsets() {
+                assert_eq!(
+                    PROCESS_VARIANTS.process(key, &processes),
+                    &process_ingredient(raw_ingredient, &processes)
+                );
+                let permutations: Vec<Ingredient> = PROCESS_VARIANTS
+                    .permutations(key, &processes)
+                    .cloned()
+                    .collect();
+                assert_eq!(permutations, permute_ingredient(raw_ingredient, &processes));
+            }
+        }
+    }
+
+    #[test]
+    fn test_rules_match_original_processing() {
+        let mut raw_parts = Vec::new();
+        for (key, raw_ingredient) in INGREDIENTS.0.iter() {
+            let raw = collect_parts(std::slice::from_ref(raw_ingredient));
+            for processes in process_subsets() {
+                let processed = PROCESS_VARIANTS.process(key, &processes);
+                assert_eq!(
+                    collect_parts(std::slice::from_ref(processed)),
+                    original_parts(&raw, &processes),
+                    "{:?} with {:?}",
+                    key,
+                    processes
+                );
+            }
+            raw_parts.extend(raw);
+        }
+
+        // The catalogue has every part that fermenting or infusing changes.
+        for part in [
+            IngredientPart::Impurity,
+            IngredientPart::Element(Element::Fire),
+            IngredientPart::Element(Element::Water),
+            IngredientPart::Element(Element::Aether),
+            IngredientPart::Element(Element::Earth),
+        ] {
+            assert!(raw_parts.contains(&part), "{:?}", part);
+        }
     }
 }
@@ -0,0 +1,125 @@
+use std::{
+    error::Error,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::models::traits::GetName;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Stage {
+    Enumerate,
+    MaximiseRecipes,
+    MaximiseAppeal,
+    MaximisePotency,
+}
+
+impl GetName for Stage {
+    fn name(&self) -> &'static str {
+        match self {
+            Stage::Enumerate => "Enumerating recipes",
+            Stage::MaximiseRecipes => "Maximising recipes",
+            Stage::MaximiseAppeal => "Maximising appeal",
+            Stage::MaximisePotency => "Maximising potency",
+        }
+    }
+}
+
+/// Receives progress updates from the long running pipeline stages.
+///
+/// Updates may arrive from several rayon worker threads at once.
+pub trait ProgressSink: Sync {
+    /// A stage started, with the number of combinations it will process if known.
+    fn stage_started(&self, _stage: Stage, _total: Option<u64>) {}
+
+    /// Some combinations of the current stage have been processed.
+    fn advanced(&self, _stage: Stage, _count: u64) {}
+
+    fn stage_finished(&self, _stage: Stage) {}
+}
+
+/// Discards all progress updates.
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {}
+
+/// Shows each stage as a progress bar on the terminal.
+#[derive(Default)]
+pub struct IndicatifProgress {
+    bar: Mutex<Option<ProgressBar>>,
+}
+
+impl IndicatifProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ProgressSink for IndicatifProgress {
+    fn stage_started(&self, stage: Stage, total: Option<u64>) {
+        let bar = match total {
+            Some(total) => {
+                let bar = ProgressBar::new(total);
+                if let Ok(style) =
+                    ProgressStyle::with_template("{msg} [{bar:40}] {pos}/{len} ({eta})")
+                {
+                    bar.set_style(style.progress_chars("=> "));
+                }
+                bar
+            }
+            None => ProgressBar::new_spinner(),
+        };
+        bar.set_message(stage.name());
+        *self.bar.lock().unwrap() = Some(bar);
+    }
+
+    fn advanced(&self, _stage: Stage, count: u64) {
+        if let Some(bar) = self.bar.lock().unwrap().as_ref() {
+            bar.inc(count);
+        }
+    }
+
+    fn stage_finished(&self, stage: Stage) {
+        if let Some(bar) = self.bar.lock().unwrap().take() {
+            bar.finish_with_message(format!("{} done", stage.name()));
+        }
+    }
+}
+
+/// Shared flag used to stop a running pipeline early.
+///
+/// Clones share the same flag, so one can be handed to another thread (for
+/// example a signal handler) while the pipeline checks the other.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Returned by a pipeline stage that stopped because it was cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl Error for Cancelled {}
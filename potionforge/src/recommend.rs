@@ -4,15 +4,21 @@ use highs::{Col, HighsModelStatus, RowProblem, Sense};
 use itertools::Itertools;
 
 use crate::models::{
-    traits::GetByKey, AlchemistAttribute, BrandingCategory, Department, IngredientKey,
-    MarketCondition, PotionKindKey, Recipe, POTION_KINDS,
+    traits::GetByKey, Alchemist, AlchemistAttribute, BrandingCategory, Department, IngredientKey,
+    MarketCondition, PotionKindKey, Pricing, Recipe, POTION_KINDS,
 };
 
 pub type IngredientCounts = HashMap<IngredientKey, i32>;
 pub type AlchemistAttributes = HashMap<AlchemistAttribute, i32>;
+pub type AlchemistRoster = Vec<Alchemist>;
 pub type MarketConditions = HashMap<PotionKindKey, Vec<MarketCondition>>;
 pub type BrandingCounts = HashMap<BrandingCategory, i32>;
 
+/// Most recipes a department can offer.
+pub const DEPARTMENT_CAPACITY: usize = 5;
+/// Fewest recipes an available ingredient can be used in.
+pub const MIN_UTILISATION: i32 = 1;
+
 #[derive(Debug)]
 pub struct RecommendConfig {
     pub available_ingredients: IngredientCounts,
@@ -20,12 +26,31 @@ pub struct RecommendConfig {
     pub potions: Vec<PotionKindKey>,
 }
 
+/// Roster equivalent to attribute counts applied to every department: one
+/// alchemist per count of each attribute, working in all departments.
+pub fn roster_from_attributes(attributes: &AlchemistAttributes) -> AlchemistRoster {
+    attributes
+        .iter()
+        .flat_map(|(attribute, &count)| {
+            (0..count).map(move |i| Alchemist {
+                name: format!("{:?} {}", attribute, i + 1),
+                attributes: vec![attribute.clone()],
+                departments: vec![
+                    Department::Health,
+                    Department::Sourcery,
+                    Department::Provisions,
+                ],
+            })
+        })
+        .collect()
+}
+
 /// Check whether two floats a and b are within epsilon of each other.
-fn nearly_equal(a: f64, b: f64, epsilon: f64) -> bool {
+pub(crate) fn nearly_equal(a: f64, b: f64, epsilon: f64) -> bool {
     (a - b).abs() < epsilon
 }
 
-fn create_binary_columns(pb: &mut RowProblem, objectives: &Vec<f64>) -> Vec<Col> {
+pub(crate) fn create_binary_columns(pb: &mut RowProblem, objectives: &Vec<f64>) -> Vec<Col> {
     let num_columns = objectives.len();
     let mut columns = Vec::with_capacity(num_columns);
     for (_, objective) in (0..num_columns).zip(objectives) {
@@ -35,7 +60,7 @@ fn create_binary_columns(pb: &mut RowProblem, objectives: &Vec<f64>) -> Vec<Col>
     columns
 }
 
-fn create_ingredient_constraints(
+pub(crate) fn create_ingredient_constraints(
     pb: &mut RowProblem,
     columns: &[Col],
     recipes: &[Recipe],
@@ -55,14 +80,7 @@ fn create_ingredient_constraints(
         let factors: Vec<(_, f64)> = columns
             .iter()
             .zip(recipes.iter())
-            .map(|(column, recipe)| {
-                let contains_key = recipe
-                    .ingredients
-                    .iter()
-                    .any(|ingredient| ingredient.key == *ingredient_key);
-
-                (*column, if contains_key { 1. } else { 0. })
-            })
+            .map(|(column, recipe)| (*column, ingredient_uses(recipe, ingredient_key) as f64))
             .collect();
 
         // Create a constraint for the ingredient.
@@ -70,13 +88,23 @@ fn create_ingredient_constraints(
     }
 }
 
-fn create_potion_kind_constraints(
+/// How many times the recipe uses the ingredient.
+pub(crate) fn ingredient_uses(recipe: &Recipe, key: &IngredientKey) -> i32 {
+    recipe
+        .ingredients
+        .iter()
+        .filter(|ingredient| ingredient.key == *key)
+        .count() as i32
+}
+
+pub(crate) fn create_potion_kind_constraints(
     pb: &mut RowProblem,
     columns: &[Col],
     recipes: &[Recipe],
     potions: &[PotionKindKey],
 ) {
-    // No more than one of each potion kind.
+    // No more than one of each potion kind. Each pricing of a recipe is a
+    // separate candidate, so this also picks a single pricing.
     for (potion_kind_key, _) in POTION_KINDS.iter() {
         let upper_bound = 1.;
         let lower_bound = if potions.contains(potion_kind_key) {
@@ -105,7 +133,12 @@ fn create_potion_kind_constraints(
     }
 }
 
-fn create_department_constraints(pb: &mut RowProblem, columns: &[Col], recipes: &[Recipe]) {
+pub(crate) fn create_department_constraints(
+    pb: &mut RowProblem,
+    columns: &[Col],
+    recipes: &[Recipe],
+    min_recipes: f64,
+) {
     let departments = [
         Department::Health,
         Department::Sourcery,
@@ -113,8 +146,8 @@ fn create_department_constraints(pb: &mut RowProblem, columns: &[Col], recipes:
     ];
 
     for &department in departments.iter() {
-        let upper_bound = 5.;
-        let lower_bound = 1.;
+        let upper_bound = DEPARTMENT_CAPACITY as f64;
+        let lower_bound = min_recipes;
 
         let factors: Vec<(_, f64)> = columns
             .iter()
@@ -137,12 +170,12 @@ fn create_department_constraints(pb: &mut RowProblem, columns: &[Col], recipes:
     }
 }
 
-fn create_number_constraints(pb: &mut RowProblem, columns: &[Col], min_recipes: i32) {
+pub(crate) fn create_number_constraints(pb: &mut RowProblem, columns: &[Col], min_recipes: i32) {
     let factors: Vec<(Col, f64)> = columns.iter().map(|&column| (column, 1.)).collect();
     pb.add_row((min_recipes as f64).., factors);
 }
 
-fn create_appeal_constraints(
+pub(crate) fn create_appeal_constraints(
     pb: &mut RowProblem,
     columns: &[Col],
     possible_recipes: &[Recipe],
@@ -157,6 +190,98 @@ fn create_appeal_constraints(
     pb.add_row(min_appeal as f64.., factors);
 }
 
+/// Whether the recipe is the bulk offer of a recipe priced by a price model,
+/// which has a standard priced twin.
+pub(crate) fn is_priced_bulk(recipe: &Recipe) -> bool {
+    recipe.pricing == Pricing::Bulk && recipe.revenue.is_some()
+}
+
+/// Leave priced bulk offers out of the portfolio, except the given one, so
+/// the appeal stage stays indifferent to pricing and the revenue stage weighs
+/// the appeal bulk pricing gains against the revenue it loses.
+pub(crate) fn create_standard_pricing_constraints(
+    pb: &mut RowProblem,
+    columns: &[Col],
+    possible_recipes: &[Recipe],
+    except: Option<usize>,
+) {
+    let factors: Vec<(Col, f64)> = columns
+        .iter()
+        .zip(possible_recipes.iter())
+        .enumerate()
+        .filter(|&(i, (_, recipe))| is_priced_bulk(recipe) && Some(i) != except)
+        .map(|(_, (&column, _))| (column, 1.))
+        .collect();
+
+    if !factors.is_empty() {
+        pb.add_row(..=0., factors);
+    }
+}
+
+/// What a portfolio is optimised for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Objective {
+    Recipes,
+    Appeal,
+    /// Potency, or revenue when recipes are priced.
+    Potency,
+}
+
+/// Maximise the objective with one recipe forced in or out of the portfolio,
+/// returning the optimum and the indices of the selected recipes. None if no
+/// portfolio satisfies the constraints.
+pub(crate) fn maximise_fixed(
+    possible_recipes: &[Recipe],
+    config: &RecommendConfig,
+    objective: Objective,
+    min_recipes: i32,
+    min_appeal: Option<i32>,
+    (fixed, included): (usize, bool),
+) -> Option<(i32, Vec<usize>)> {
+    let mut pb = RowProblem::default();
+
+    let objectives = match objective {
+        Objective::Recipes => vec![1.0; possible_recipes.len()],
+        Objective::Appeal => create_appeal_objectives(possible_recipes),
+        Objective::Potency => create_potency_objectives(possible_recipes),
+    };
+    let columns = create_binary_columns(&mut pb, &objectives);
+
+    create_ingredient_constraints(
+        &mut pb,
+        &columns,
+        possible_recipes,
+        &config.available_ingredients,
+        config.utilisation,
+    );
+    create_potion_kind_constraints(&mut pb, &columns, possible_recipes, &config.potions);
+    create_department_constraints(&mut pb, &columns, possible_recipes, 1.);
+    create_number_constraints(&mut pb, &columns, min_recipes);
+    if let Some(min_appeal) = min_appeal {
+        create_appeal_constraints(&mut pb, &columns, possible_recipes, min_appeal);
+    }
+    if objective == Objective::Appeal {
+        let except = if included { Some(fixed) } else { None };
+        create_standard_pricing_constraints(&mut pb, &columns, possible_recipes, except);
+    }
+    let bound = if included { 1. } else { 0. };
+    pb.add_row(bound..=bound, [(columns[fixed], 1.)]);
+
+    let solved = pb.optimise(Sense::Maximise).solve();
+    if solved.status() != HighsModelStatus::Optimal {
+        return None;
+    }
+
+    let selected: Vec<usize> = solved
+        .get_solution()
+        .columns()
+        .iter()
+        .positions(|&value| nearly_equal(value, 1., 1e-6))
+        .collect();
+    let total: f64 = selected.iter().map(|&i| objectives[i]).sum();
+    Some((total.round() as i32, selected))
+}
+
 fn create_appeal_objectives(possible_recipes: &[Recipe]) -> Vec<f64> {
     possible_recipes
         .iter()
@@ -164,10 +289,11 @@ fn create_appeal_objectives(possible_recipes: &[Recipe]) -> Vec<f64> {
         .collect_vec()
 }
 
+/// Revenue of recipes scored with a price model, otherwise their potency.
 fn create_potency_objectives(possible_recipes: &[Recipe]) -> Vec<f64> {
     possible_recipes
         .iter()
-        .map(|recipe| recipe.overall_potency as f64)
+        .map(|recipe| recipe.revenue.unwrap_or(recipe.overall_potency) as f64)
         .collect_vec()
 }
 
@@ -177,9 +303,6 @@ pub fn maximise_recipes(
     utilisation: i32,
     potions: &[PotionKindKey],
 ) -> i32 {
-    // TODO: Signal progress to the calling process.
-    // println!("Maximising recipes.");
-
     // Create the problem.
     let mut pb = RowProblem::default();
 
@@ -198,7 +321,7 @@ pub fn maximise_recipes(
         utilisation,
     );
     create_potion_kind_constraints(&mut pb, &columns, &possible_recipes, &potions);
-    create_department_constraints(&mut pb, &columns, &possible_recipes);
+    create_department_constraints(&mut pb, &columns, possible_recipes, 1.);
 
     // Create a Highs model to be optimised.
     let model = pb.optimise(Sense::Maximise);
@@ -227,9 +350,6 @@ pub fn maximise_appeal(
     potions: &[PotionKindKey],
     min_recipes: i32,
 ) -> i32 {
-    // TODO: Signal progress to the calling process.
-    // println!("Maximising appeal.");
-
     // Create the problem.
     let mut pb = RowProblem::default();
 
@@ -248,8 +368,9 @@ pub fn maximise_appeal(
         utilisation,
     );
     create_potion_kind_constraints(&mut pb, &columns, &possible_recipes, &potions);
-    create_department_constraints(&mut pb, &columns, &possible_recipes);
+    create_department_constraints(&mut pb, &columns, possible_recipes, 1.);
     create_number_constraints(&mut pb, &columns, min_recipes);
+    create_standard_pricing_constraints(&mut pb, &columns, possible_recipes, None);
 
     // Create a Highs model to be optimised.
     let model = pb.optimise(Sense::Maximise);
@@ -281,13 +402,10 @@ pub fn maximise_potency(
     min_recipes: i32,
     min_appeal: i32,
 ) -> Vec<Recipe> {
-    // TODO: Signal progress to the calling process.
-    // println!("Maximising appeal.");
-
     // Create the problem.
     let mut pb = RowProblem::default();
 
-    // Objective function: maximize the combined potency of all recipes
+    // Objective function: maximize the combined potency (or revenue) of all recipes
     let objectives = create_potency_objectives(possible_recipes);
 
     // The columns: a binary variable for each recipe with coeffecient 1.0.
@@ -302,7 +420,7 @@ pub fn maximise_potency(
         utilisation,
     );
     create_potion_kind_constraints(&mut pb, &columns, &possible_recipes, &potions);
-    create_department_constraints(&mut pb, &columns, &possible_recipes);
+    create_department_constraints(&mut pb, &columns, possible_recipes, 1.);
     create_number_constraints(&mut pb, &columns, min_recipes);
     create_appeal_constraints(&mut pb, &columns, &possible_recipes, min_appeal);
 
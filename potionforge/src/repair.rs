@@ -0,0 +1,291 @@
+use std::sync::Arc;
+
+use crate::{
+    core::is_combination_valid,
+    enumerate::{MAX_ARCANE_POWER, MIN_ARCANE_POWER},
+    models::{Ingredient, IngredientKey, Process, Recipe},
+    process::VariantTable,
+    simulate::{simulate, SimulateConfig},
+};
+
+#[derive(Debug, Clone)]
+pub struct RepairConfig {
+    /// Ingredients that may be added.
+    pub ingredients: Vec<IngredientKey>,
+    /// Processes that may be applied, to added ingredients or existing ones.
+    pub processes: Vec<Process>,
+    /// Allow adding an ingredient already in the list.
+    pub repeated_ingredients: bool,
+    /// Processed variants of the catalogue under the rules in use.
+    pub variants: Arc<VariantTable>,
+}
+
+/// A single change to a list of ingredients. Removing or reprocessing an
+/// ingredient the list holds more than once changes only its first copy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edit {
+    Add(Ingredient),
+    Remove(Ingredient),
+    /// Use a different process chain for an ingredient, such as infusing it to
+    /// swap its elements.
+    Reprocess {
+        from: Ingredient,
+        to: Ingredient,
+    },
+}
+
+/// An edit and the potion it produces.
+#[derive(Debug, Clone)]
+pub struct Repair {
+    pub edit: Edit,
+    pub recipe: Recipe,
+}
+
+/// Find single edits to the ingredients that produce a potion, best appeal
+/// first and then best potency.
+///
+/// Intended for ingredients that make no potion, for example because their
+/// elements cancel or their main effects tie. Adding an ingredient is only
+/// suggested below `MAX_ARCANE_POWER` ingredients, and removing one while at
+/// least `MIN_ARCANE_POWER` remain.
+pub fn suggest_repairs(
+    ingredients: &[Ingredient],
+    simulate_config: &SimulateConfig,
+    config: &RepairConfig,
+) -> Vec<Repair> {
+    let mut edits = Vec::new();
+
+    let size = ingredients.len() as i64;
+
+    for key in &config.ingredients {
+        if size >= MAX_ARCANE_POWER {
+            break;
+        }
+        if !config.repeated_ingredients
+            && ingredients.iter().any(|ingredient| ingredient.key == *key)
+        {
+            continue;
+        }
+        for variant in config.variants.permutations(key, &config.processes) {
+            edits.push(Edit::Add(variant.clone()));
+        }
+    }
+
+    for (i, ingredient) in ingredients.iter().enumerate() {
+        // Each copy of an ingredient gives the same edits.
+        if ingredients[..i].contains(ingredient) {
+            continue;
+        }
+        for variant in config
+            .variants
+            .permutations(&ingredient.key, &config.processes)
+        {
+            if variant.parts != ingredient.parts {
+                edits.push(Edit::Reprocess {
+                    from: ingredient.clone(),
+                    to: variant.clone(),
+                });
+            }
+        }
+        if size > MIN_ARCANE_POWER {
+            edits.push(Edit::Remove(ingredient.clone()));
+        }
+    }
+
+    let mut repairs: Vec<Repair> = edits
+        .into_iter()
+        .filter_map(|edit| {
+            let edited = apply_edit(ingredients, &edit);
+            if !is_combination_valid(&edited, config.repeated_ingredients) {
+                return None;
+            }
+            let recipe = simulate(&edited, simulate_config)?;
+            Some(Repair { edit, recipe })
+        })
+        .collect();
+
+    repairs.sort_by(|a, b| {
+        b.recipe
+            .overall_appeal
+            .cmp(&a.recipe.overall_appeal)
+            .then(b.recipe.overall_potency.cmp(&a.recipe.overall_potency))
+    });
+    repairs
+}
+
+fn apply_edit(ingredients: &[Ingredient], edit: &Edit) -> Vec<Ingredient> {
+    let mut edited = ingredients.to_vec();
+    match edit {
+        Edit::Add(added) => edited.push(added.clone()),
+        Edit::Remove(removed) => {
+            if let Some(i) = edited.iter().position(|ingredient| ingredient == removed) {
+                edited.remove(i);
+            }
+        }
+        Edit::Reprocess { from, to } => {
+            if let Some(i) = edited.iter().position(|ingredient| ingredient == from) {
+                edited[i] = to.clone();
+            }
+        }
+    }
+    edited
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+
+    use crate::{
+        models::{traits::GetByKey, INGREDIENTS},
+        process::{process_ingredient, PROCESS_VARIANTS},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_suggest_repairs() {
+        let simulate_config = SimulateConfig::default();
+        let config = RepairConfig {
+            ingredients: INGREDIENTS.0.iter().map(|(key, _)| *key).collect(),
+            processes: vec![
+                Process::Crush,
+                Process::Blanch,
+                Process::Dry,
+                Process::Pickle,
+                Process::Ferment,
+                Process::Infuse,
+            ],
+            repeated_ingredients: false,
+            variants: PROCESS_VARIANTS.clone(),
+        };
+
+        // Pairs of raw ingredients that make no potion.
+        let raw: Vec<Ingredient> = INGREDIENTS
+            .0
+            .iter()
+            .map(|(key, _)| process_ingredient(INGREDIENTS.get_by_key(key), &vec![]))
+            .collect();
+        let failing: Vec<Vec<Ingredient>> = raw
+            .iter()
+            .enumerate()
+            .flat_map(|(i, a)| raw[i + 1..].iter().map(move |b| vec![a.clone(), b.clone()]))
+            .filter(|pair| simulate(pair, &simulate_config).is_none())
+            .collect();
+        assert!(!failing.is_empty());
+
+        let mut repaired = 0;
+        for pair in &failing {
+            let repairs = suggest_repairs(pair, &simulate_config, &config);
+            if !repairs.is_empty() {
+                repaired += 1;
+            }
+            for repair in &repairs {
+                assert!(!matches!(repair.edit, Edit::Remove(_)));
+                let edited = apply_edit(pair, &repair.edit);
+                assert!(simulate(&edited, &simulate_config).is_some());
+            }
+            assert!(repairs.windows(2).all(|pair| {
+                (
+                    pair[0].recipe.overall_appeal,
+                    pair[0].recipe.overall_potency,
+                ) >= (
+                    pair[1].recipe.overall_appeal,
+                    pair[1].recipe.overall_potency,
+                )
+            }));
+        }
+        assert!(repaired > 0);
+    }
+
+    #[test]
+    fn test_edit_repeated_ingredient() {
+        let catnip = INGREDIENTS.get_by_key(&IngredientKey::Catnip).clone();
+        let sage = INGREDIENTS.get_by_key(&IngredientKey::Sage).clone();
+        let infused = PROCESS_VARIANTS
+            .process(&IngredientKey::Catnip, &[Process::Infuse])
+            .clone();
+        let ingredients = vec![catnip.clone(), catnip.clone(), sage.clone()];
+
+        assert_eq!(
+            apply_edit(&ingredients, &Edit::Remove(catnip.clone())),
+            vec![catnip.clone(), sage.clone()]
+        );
+        assert_eq!(
+            apply_edit(
+                &ingredients,
+                &Edit::Reprocess {
+                    from: catnip.clone(),
+                    to: infused.clone(),
+                }
+            ),
+            vec![infused, catnip, sage]
+        );
+
+        let simulate_config = SimulateConfig::default();
+        let config = RepairConfig {
+            ingredients: INGREDIENTS.0.iter().map(|(key, _)| *key).collect(),
+            processes: vec![Process::Ferment, Process::Infuse],
+            repeated_ingredients: true,
+            variants: PROCESS_VARIANTS.clone(),
+        };
+        let repairs = suggest_repairs(&ingredients, &simulate_config, &config);
+        assert!(!repairs.is_empty());
+        for (i, repair) in repairs.iter().enumerate() {
+            assert!(!repairs[..i].iter().any(|other| other.edit == repair.edit));
+            let edited = apply_edit(&ingredients, &repair.edit);
+            assert_eq!(
+                simulate(&edited, &simulate_config),
+                Some(repair.recipe.clone())
+            );
+        }
+    }
+
+    #[test]
+    fn test_repairs_keep_arcane_power() {
+        let simulate_config = SimulateConfig::default();
+        let config = RepairConfig {
+            ingredients: INGREDIENTS.0.iter().map(|(key, _)| *key).collect(),
+            processes: vec![Process::Crush],
+            repeated_ingredients: false,
+            variants: PROCESS_VARIANTS.clone(),
+        };
+        let raw: Vec<Ingredient> = INGREDIENTS
+            .0
+            .iter()
+            .map(|(key, _)| process_ingredient(INGREDIENTS.get_by_key(key), &vec![]))
+            .collect();
+        let failing = |size: usize| {
+            raw.iter()
+                .cloned()
+                .combinations(size)
+                .filter(|ingredients| simulate(ingredients, &simulate_config).is_none())
+        };
+        let is_add = |repair: &Repair| matches!(repair.edit, Edit::Add(_));
+
+        // Below the arcane power an ingredient can be added.
+        assert!(failing(MAX_ARCANE_POWER as usize - 1).any(|ingredients| {
+            suggest_repairs(&ingredients, &simulate_config, &config)
+                .iter()
+                .any(is_add)
+        }));
+
+        // At the arcane power nothing is added, even an ingredient that would
+        // make a potion.
+        let full = failing(MAX_ARCANE_POWER as usize)
+            .find(|ingredients| {
+                raw.iter()
+                    .filter(|ingredient| !ingredients.contains(ingredient))
+                    .any(|ingredient| {
+                        let mut added = ingredients.clone();
+                        added.push(ingredient.clone());
+                        simulate(&added, &simulate_config).is_some()
+                    })
+            })
+            .unwrap();
+        let repairs = suggest_repairs(&full, &simulate_config, &config);
+        assert!(!repairs.iter().any(is_add));
+        for repair in &repairs {
+            assert!(apply_edit(&full, &repair.edit).len() <= MAX_ARCANE_POWER as usize);
+        }
+    }
+}
@@ -0,0 +1,456 @@
+use std::{error::Error, fmt};
+
+use highs::{Col, HighsModelStatus, RowProblem, Sense};
+use serde::Deserialize;
+
+use crate::{
+    core::is_recipe_reasonable,
+    enumerate::CandidateFilter,
+    models::{Recipe, RecipeIntrinsics},
+    progress::{CancellationToken, Cancelled},
+    recommend::{
+        create_department_constraints, create_ingredient_constraints, create_number_constraints,
+        create_potion_kind_constraints, maximise_recipes, nearly_equal, MarketConditions,
+        RecommendConfig,
+    },
+    simulate::{score_offers, SimulateConfig},
+};
+
+/// One possible outcome of the market, weighted by how likely it is.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MarketScenario {
+    pub name: String,
+    pub probability: f64,
+    pub market: MarketConditions,
+}
+
+/// How the appeal of a portfolio is combined over the scenarios.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScenarioObjective {
+    /// Probability weighted mean of the appeal in each scenario.
+    #[default]
+    Expected,
+    /// Appeal in the least favourable scenario.
+    WorstCase,
+}
+
+/// A recipe with its appeal in each scenario.
+#[derive(Debug, Clone)]
+struct ScenarioCandidate {
+    /// The recipe scored with its expected appeal.
+    recipe: Recipe,
+    appeals: Vec<i32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScenarioRecommendation {
+    /// Selected recipes, with their expected appeal as overall appeal.
+    pub recipes: Vec<Recipe>,
+    /// Total appeal of the selected recipes in each scenario.
+    pub scenario_appeals: Vec<i32>,
+    pub expected_appeal: f64,
+}
+
+/// Scenarios that cannot be weighted, or a cancelled recommendation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScenarioError {
+    NoScenarios,
+    /// A scenario has a negative, infinite or NaN probability.
+    InvalidProbability(String, f64),
+    /// The probabilities sum to zero.
+    ZeroProbability,
+    Cancelled,
+}
+
+impl From<Cancelled> for ScenarioError {
+    fn from(_: Cancelled) -> Self {
+        ScenarioError::Cancelled
+    }
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::NoScenarios => write!(f, "no market scenarios are listed"),
+            ScenarioError::InvalidProbability(name, probability) => write!(
+                f,
+                "scenario {} has a probability of {}, expected a finite number of at least 0",
+                name, probability
+            ),
+            ScenarioError::ZeroProbability => {
+                write!(f, "scenario probabilities must not all be zero")
+            }
+            ScenarioError::Cancelled => write!(f, "{}", Cancelled),
+        }
+    }
+}
+
+impl Error for ScenarioError {}
+
+/// Check the scenarios can be weighted by their probabilities.
+pub fn validate_scenarios(scenarios: &[MarketScenario]) -> Result<(), ScenarioError> {
+    if scenarios.is_empty() {
+        return Err(ScenarioError::NoScenarios);
+    }
+    if let Some(scenario) = scenarios
+        .iter()
+        .find(|scenario| !scenario.probability.is_finite() || scenario.probability < 0.)
+    {
+        return Err(ScenarioError::InvalidProbability(
+            scenario.name.clone(),
+            scenario.probability,
+        ));
+    }
+    if scenarios.iter().all(|scenario| scenario.probability == 0.) {
+        return Err(ScenarioError::ZeroProbability);
+    }
+    Ok(())
+}
+
+/// Recommend recipes when the market is uncertain.
+///
+/// Like `core::recommend`, the number of recipes is maximised first, then the
+/// appeal combined over the scenarios according to the objective and then
+/// potency (or revenue). Probabilities are normalised to sum to one. The
+/// market of the simulate configuration is ignored.
+pub fn recommend_scenarios(
+    intrinsics: &[RecipeIntrinsics],
+    simulate_config: &SimulateConfig,
+    filters: &[CandidateFilter],
+    scenarios: &[MarketScenario],
+    objective: ScenarioObjective,
+    config: &RecommendConfig,
+    cancel: &CancellationToken,
+) -> Result<ScenarioRecommendation, ScenarioError> {
+    validate_scenarios(scenarios)?;
+
+    let total_probability: f64 = scenarios.iter().map(|scenario| scenario.probability).sum();
+    let weights: Vec<f64> = scenarios
+        .iter()
+        .map(|scenario| scenario.probability / total_probability)
+        .collect();
+    let candidates = score_scenarios(intrinsics, simulate_config, filters, scenarios, &weights);
+    let recipes: Vec<Recipe> = candidates
+        .iter()
+        .map(|candidate| candidate.recipe.clone())
+        .collect();
+
+    let recipe_count = maximise_recipes(
+        &recipes,
+        &config.available_ingredients,
+        config.utilisation,
+        &config.potions,
+    );
+
+    if cancel.is_cancelled() {
+        return Err(ScenarioError::Cancelled);
+    }
+
+    let (appeal, _) = solve(&candidates, &weights, objective, config, recipe_count, None);
+
+    if cancel.is_cancelled() {
+        return Err(ScenarioError::Cancelled);
+    }
+
+    let (_, selected) = solve(
+        &candidates,
+        &weights,
+        objective,
+        config,
+        recipe_count,
+        Some(appeal),
+    );
+
+    let scenario_appeals = (0..scenarios.len())
+        .map(|s| selected.iter().map(|candidate| candidate.appeals[s]).sum())
+        .collect();
+    let expected_appeal = selected
+        .iter()
+        .map(|candidate| expected(&candidate.appeals, &weights))
+        .sum();
+
+    Ok(ScenarioRecommendation {
+        recipes: selected
+            .into_iter()
+            .map(|candidate| candidate.recipe)
+            .collect(),
+        scenario_appeals,
+        expected_appeal,
+    })
+}
+
+fn expected(appeals: &[i32], weights: &[f64]) -> f64 {
+    appeals
+        .iter()
+        .zip(weights)
+        .map(|(&appeal, weight)| appeal as f64 * weight)
+        .sum()
+}
+
+/// Score every recipe under each scenario, dropping those the filters reject
+/// at their expected appeal.
+fn score_scenarios(
+    intrinsics: &[RecipeIntrinsics],
+    simulate_config: &SimulateConfig,
+    filters: &[CandidateFilter],
+    scenarios: &[MarketScenario],
+    weights: &[f64],
+) -> Vec<ScenarioCandidate> {
+    let scenario_configs: Vec<SimulateConfig> = scenarios
+        .iter()
+        .map(|scenario| SimulateConfig {
+            market_conditions: scenario.market.clone(),
+            ..simulate_config.clone()
+        })
+        .collect();
+
+    intrinsics
+        .iter()
+        .flat_map(|intrinsics| {
+            let offers: Vec<Vec<Recipe>> = scenario_configs
+                .iter()
+                .map(|scenario_config| score_offers(intrinsics, scenario_config))
+                .collect();
+
+            // Every scenario offers the same pricings in the same order.
+            (0..offers[0].len())
+                .map(|i| {
+                    let appeals: Vec<i32> = offers
+                        .iter()
+                        .map(|scenario_offers| scenario_offers[i].overall_appeal)
+                        .collect();
+                    let mut recipe = offers[0][i].clone();
+                    recipe.overall_appeal = expected(&appeals, weights).round() as i32;
+                    ScenarioCandidate { recipe, appeals }
+                })
+                .collect::<Vec<_>>()
+        })
+        .filter(|candidate| is_recipe_reasonable(&candidate.recipe, filters))
+        .collect()
+}
+
+/// Maximise the scenario appeal, or potency once the scenario appeal is
+/// bounded below, returning the optimum and the selected candidates.
+fn solve(
+    candidates: &[ScenarioCandidate],
+    weights: &[f64],
+    objective: ScenarioObjective,
+    config: &RecommendConfig,
+    min_recipes: i32,
+    min_appeal: Option<f64>,
+) -> (f64, Vec<ScenarioCandidate>) {
+    let recipes: Vec<Recipe> = candidates
+        .iter()
+        .map(|candidate| candidate.recipe.clone())
+        .collect();
+    let mut pb = RowProblem::default();
+
+    let optimise_appeal = min_appeal.is_none();
+    let columns: Vec<Col> = candidates
+        .iter()
+        .map(|candidate| {
+            let factor = match (optimise_appeal, objective) {
+                (true, ScenarioObjective::Expected) => expected(&candidate.appeals, weights),
+                (true, ScenarioObjective::WorstCase) => 0.,
+                (false, _) => candidate
+                    .recipe
+                    .revenue
+                    .unwrap_or(candidate.recipe.overall_potency)
+                    as f64,
+            };
+            pb.add_integer_column(factor, 0..1)
+        })
+        .collect();
+
+    // The worst case appeal is a free variable no greater than the appeal
+    // of any scenario.
+    let worst_case = match objective {
+        ScenarioObjective::WorstCase => {
+            let factor = if optimise_appeal { 1. } else { 0. };
+            Some(pb.add_column::<f64, _>(factor, ..))
+        }
+        ScenarioObjective::Expected => None,
+    };
+
+    create_ingredient_constraints(
+        &mut pb,
+        &columns,
+        &recipes,
+        &config.available_ingredients,
+        config.utilisation,
+    );
+    create_potion_kind_constraints(&mut pb, &columns, &recipes, &config.potions);
+    create_department_constraints(&mut pb, &columns, &recipes, 1.);
+    create_number_constraints(&mut pb, &columns, min_recipes);
+
+    if let Some(worst_case) = worst_case {
+        for s in 0..weights.len() {
+            let mut factors: Vec<(Col, f64)> = columns
+                .iter()
+                .zip(candidates)
+                .map(|(&column, candidate)| (column, candidate.appeals[s] as f64))
+                .collect();
+            factors.push((worst_case, -1.));
+            pb.add_row(0.., factors);
+        }
+    }
+
+    if let Some(min_appeal) = min_appeal {
+        let factors: Vec<(Col, f64)> = match worst_case {
+            Some(worst_case) => vec![(worst_case, 1.)],
+            None => columns
+                .iter()
+                .zip(candidates)
+                .map(|(&column, candidate)| (column, expected(&candidate.appeals, weights)))
+                .collect(),
+        };
+        // Allow for floating point error in the expected appeal.
+        pb.add_row(min_appeal - 1e-6.., factors);
+    }
+
+    let solved = pb.optimise(Sense::Maximise).solve();
+
+    // Check the solver finished and solution is proven optimal.
+    assert_eq!(solved.status(), HighsModelStatus::Optimal);
+
+    let solution = solved.get_solution();
+    let values = solution.columns();
+
+    let selected: Vec<ScenarioCandidate> = values
+        .iter()
+        .zip(candidates)
+        .filter(|(value, _)| nearly_equal(**value, 1., 1e-6))
+        .map(|(_, candidate)| candidate.clone())
+        .collect();
+
+    let optimum = match worst_case {
+        Some(_) => values[candidates.len()],
+        None => selected
+            .iter()
+            .map(|candidate| expected(&candidate.appeals, weights))
+            .sum(),
+    };
+
+    (optimum, selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        core::enumerate,
+        enumerate::default_filters,
+        models::{IngredientKey, MarketCondition, PotionKindKey, Process, POTION_KINDS},
+        progress::NoProgress,
+        testdata::{enumerate_config, TEST_INGREDIENTS},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_recommend_scenarios() {
+        let ingredients = TEST_INGREDIENTS.to_vec();
+        let enumerate_config = enumerate_config(
+            &ingredients,
+            3,
+            vec![Process::Crush, Process::Dry, Process::Blanch],
+        );
+        let intrinsics =
+            enumerate(&enumerate_config, &NoProgress, &CancellationToken::new()).unwrap();
+
+        let simulate_config = SimulateConfig::default();
+        let config = RecommendConfig {
+            available_ingredients: ingredients.iter().map(|&key| (key, 1)).collect(),
+            utilisation: 3,
+            potions: vec![],
+        };
+
+        let mut boom = MarketConditions::new();
+        for (key, _) in POTION_KINDS.iter() {
+            boom.insert(key.clone(), vec![MarketCondition::HighDemand]);
+        }
+        let mut bust = MarketConditions::new();
+        bust.insert(PotionKindKey::Mana, vec![MarketCondition::LowDemand]);
+        let scenarios = vec![
+            MarketScenario {
+                name: "Boom".to_string(),
+                probability: 3.,
+                market: boom,
+            },
+            MarketScenario {
+                name: "Bust".to_string(),
+                probability: 1.,
+                market: bust,
+            },
+        ];
+
+        let recommend = |objective| {
+            recommend_scenarios(
+                &intrinsics,
+                &simulate_config,
+                &enumerate_config.filters,
+                &scenarios,
+                objective,
+                &config,
+                &CancellationToken::new(),
+            )
+            .unwrap()
+        };
+        let expected = recommend(ScenarioObjective::Expected);
+        let worst_case = recommend(ScenarioObjective::WorstCase);
+
+        for result in [&expected, &worst_case] {
+            assert!(!result.recipes.is_empty());
+            let expected_appeal =
+                0.75 * result.scenario_appeals[0] as f64 + 0.25 * result.scenario_appeals[1] as f64;
+            assert!(nearly_equal(result.expected_appeal, expected_appeal, 1e-6));
+            // Demand is only ever higher in the boom.
+            assert!(result.scenario_appeals[0] >= result.scenario_appeals[1]);
+        }
+
+        let worst =
+            |result: &ScenarioRecommendation| *result.scenario_appeals.iter().min().unwrap();
+        assert!(worst(&worst_case) >= worst(&expected));
+        assert!(expected.expected_appeal >= worst_case.expected_appeal - 1e-6);
+    }
+
+    #[test]
+    fn test_recommend_scenarios_rejects_invalid_probabilities() {
+        let simulate_config = SimulateConfig::default();
+        let config = RecommendConfig {
+            available_ingredients: [(IngredientKey::Sage, 1)].into_iter().collect(),
+            utilisation: 3,
+            potions: vec![],
+        };
+        let scenarios = |probabilities: &[f64]| -> Vec<MarketScenario> {
+            probabilities
+                .iter()
+                .enumerate()
+                .map(|(i, &probability)| MarketScenario {
+                    name: format!("Scenario {}", i),
+                    probability,
+                    market: MarketConditions::new(),
+                })
+                .collect()
+        };
+        let recommend = |probabilities: &[f64]| {
+            recommend_scenarios(
+                &[],
+                &simulate_config,
+                &default_filters(),
+                &scenarios(probabilities),
+                ScenarioObjective::Expected,
+                &config,
+                &CancellationToken::new(),
+            )
+            .unwrap_err()
+        };
+
+        assert_eq!(recommend(&[]), ScenarioError::NoScenarios);
+        assert_eq!(recommend(&[0., 0.]), ScenarioError::ZeroProbability);
+        assert_eq!(
+            recommend(&[1., -0.5]),
+            ScenarioError::InvalidProbability("Scenario 1".to_string(), -0.5)
+        );
+    }
+}
@@ -0,0 +1,524 @@
+use crate::{
+    core::{prune_dominated, recommend, score_recipes},
+    enumerate::CandidateFilter,
+    models::{
+        BrandingCategory, MarketCondition, PotionKindKey, Recipe, RecipeIntrinsics, POTION_KINDS,
+    },
+    progress::{CancellationToken, Cancelled, NoProgress},
+    recommend::{maximise_fixed, Objective, RecommendConfig},
+    simulate::SimulateConfig,
+};
+
+/// How far a recipe is from changing whether it is recommended.
+#[derive(Debug, Clone)]
+pub struct RecipeSensitivity {
+    pub recipe: Recipe,
+    /// For a selected recipe, how much its appeal can drop before another
+    /// portfolio is better. For an unselected recipe, how much appeal it
+    /// needs to gain to enter. None if no change in its appeal alone would
+    /// change the decision.
+    pub appeal_margin: Option<i32>,
+    /// Same as the appeal margin for potency (or revenue when priced), with
+    /// appeal unchanged.
+    pub potency_margin: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConditionChange {
+    AddMarket(PotionKindKey, MarketCondition),
+    RemoveMarket(PotionKindKey, MarketCondition),
+    /// Change the level of a branding category by the amount given.
+    Branding(BrandingCategory, i32),
+}
+
+/// A change in conditions after which the portfolio is no longer optimal.
+#[derive(Debug, Clone)]
+pub struct DecisionFlip {
+    pub change: ConditionChange,
+    pub entering: Vec<Recipe>,
+    pub leaving: Vec<Recipe>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SensitivityReport {
+    /// Every recipe of the portfolio, in order. A recipe that is not among
+    /// the scored candidates has no margins.
+    pub selected: Vec<RecipeSensitivity>,
+    pub unselected: Vec<RecipeSensitivity>,
+    pub flips: Vec<DecisionFlip>,
+}
+
+/// Report how fragile a portfolio returned by `core::recommend` is.
+///
+/// Margins are found by solving again with each recipe forced out of (or
+/// into) the portfolio, keeping the number of recipes. Only the
+/// `candidate_limit` best unselected recipes by appeal are considered. Flips
+/// are found by recommending again after each single market condition or
+/// branding level change, and are only reported when the portfolio scores
+/// worse than the new recommendation.
+pub fn analyse_sensitivity(
+    intrinsics: &[RecipeIntrinsics],
+    simulate_config: &SimulateConfig,
+    filters: &[CandidateFilter],
+    config: &RecommendConfig,
+    portfolio: &[Recipe],
+    candidate_limit: usize,
+    cancel: &CancellationToken,
+) -> Result<SensitivityReport, Cancelled> {
+    // Margins are measured against every scored recipe rather than those left
+    // by dominance pruning: with a selected recipe forced out, a recipe it
+    // dominates can be the best alternative.
+    let recipes = score_recipes(intrinsics, simulate_config, filters);
+    let (selected, unselected) =
+        recipe_margins(&recipes, config, portfolio, candidate_limit, cancel)?;
+
+    let mut flips = Vec::new();
+    for change in condition_changes(simulate_config, &recipes) {
+        if cancel.is_cancelled() {
+            return Err(Cancelled);
+        }
+        let changed_config = apply_change(simulate_config, &change);
+        let changed_recipes = score_recipes(intrinsics, &changed_config, filters);
+        // The portfolio as scored under the changed conditions, or None if one
+        // of its recipes is no longer a candidate.
+        let rescored: Option<Vec<Recipe>> = portfolio
+            .iter()
+            .map(|recipe| {
+                changed_recipes
+                    .iter()
+                    .find(|other| same_offer(other, recipe))
+                    .cloned()
+            })
+            .collect();
+        let (possible_recipes, _) = prune_dominated(changed_recipes);
+        let changed = recommend(possible_recipes, config, &NoProgress, cancel)?;
+
+        // A different portfolio scoring the same as the original is a tie,
+        // not a change of decision.
+        if rescored.is_some_and(|rescored| objectives(&rescored) == objectives(&changed)) {
+            continue;
+        }
+
+        let entering: Vec<Recipe> = changed
+            .iter()
+            .filter(|recipe| !portfolio.iter().any(|other| same_offer(recipe, other)))
+            .cloned()
+            .collect();
+        let leaving: Vec<Recipe> = portfolio
+            .iter()
+            .filter(|recipe| !changed.iter().any(|other| same_offer(recipe, other)))
+            .cloned()
+            .collect();
+        if !entering.is_empty() || !leaving.is_empty() {
+            flips.push(DecisionFlip {
+                change,
+                entering,
+                leaving,
+            });
+        }
+    }
+
+    Ok(SensitivityReport {
+        selected,
+        unselected,
+        flips,
+    })
+}
+
+/// Appeal and potency margins of each recipe in the portfolio and of the
+/// `candidate_limit` best unselected recipes by appeal.
+fn recipe_margins(
+    recipes: &[Recipe],
+    config: &RecommendConfig,
+    portfolio: &[Recipe],
+    candidate_limit: usize,
+    cancel: &CancellationToken,
+) -> Result<(Vec<RecipeSensitivity>, Vec<RecipeSensitivity>), Cancelled> {
+    let recipe_count = portfolio.len() as i32;
+    let appeal: i32 = portfolio.iter().map(|recipe| recipe.overall_appeal).sum();
+    let value: i32 = portfolio.iter().map(value_of).sum();
+
+    let mut selected = Vec::new();
+    for recipe in portfolio {
+        if cancel.is_cancelled() {
+            return Err(Cancelled);
+        }
+        let index = match recipes.iter().position(|other| same_offer(other, recipe)) {
+            Some(index) => index,
+            None => {
+                selected.push(RecipeSensitivity {
+                    recipe: recipe.clone(),
+                    appeal_margin: None,
+                    potency_margin: None,
+                });
+                continue;
+            }
+        };
+        let fixed = (index, false);
+        let appeal_margin = maximise_fixed(
+            recipes,
+            config,
+            Objective::Appeal,
+            recipe_count,
+            None,
+            fixed,
+        )
+        .map(|(alternative, _)| appeal - alternative);
+        let potency_margin = maximise_fixed(
+            recipes,
+            config,
+            Objective::Potency,
+            recipe_count,
+            Some(appeal),
+            fixed,
+        )
+        .map(|(alternative, _)| value - alternative);
+        selected.push(RecipeSensitivity {
+            recipe: recipe.clone(),
+            appeal_margin,
+            potency_margin,
+        });
+    }
+
+    let mut candidates: Vec<usize> = (0..recipes.len())
+        .filter(|&i| !portfolio.iter().any(|other| same_offer(other, &recipes[i])))
+        .collect();
+    candidates.sort_by(|&a, &b| {
+        recipes[b]
+            .overall_appeal
+            .cmp(&recipes[a].overall_appeal)
+            .then(recipes[b].overall_potency.cmp(&recipes[a].overall_potency))
+    });
+    candidates.truncate(candidate_limit);
+
+    let mut unselected = Vec::new();
+    for index in candidates {
+        if cancel.is_cancelled() {
+            return Err(Cancelled);
+        }
+        let fixed = (index, true);
+        let alternative_appeal = maximise_fixed(
+            recipes,
+            config,
+            Objective::Appeal,
+            recipe_count,
+            None,
+            fixed,
+        )
+        .map(|(alternative, _)| alternative);
+        let potency_margin = match alternative_appeal {
+            Some(alternative) if alternative == appeal => maximise_fixed(
+                recipes,
+                config,
+                Objective::Potency,
+                recipe_count,
+                Some(appeal),
+                fixed,
+            )
+            .map(|(alternative, _)| value - alternative),
+            _ => None,
+        };
+        unselected.push(RecipeSensitivity {
+            recipe: recipes[index].clone(),
+            appeal_margin: alternative_appeal.map(|alternative| appeal - alternative),
+            potency_margin,
+        });
+    }
+
+    Ok((selected, unselected))
+}
+
+fn value_of(recipe: &Recipe) -> i32 {
+    recipe.revenue.unwrap_or(recipe.overall_potency)
+}
+
+/// Number of recipes, appeal and potency (or revenue when priced) of a
+/// portfolio, in the order `core::recommend` maximises them.
+fn objectives(portfolio: &[Recipe]) -> (usize, i32, i32) {
+    (
+        portfolio.len(),
+        portfolio.iter().map(|recipe| recipe.overall_appeal).sum(),
+        portfolio.iter().map(value_of).sum(),
+    )
+}
+
+/// Whether two recipes are the same ingredients offered at the same pricing,
+/// whatever their appeal.
+fn same_offer(a: &Recipe, b: &Recipe) -> bool {
+    a.potion_kind_key == b.potion_kind_key
+        && a.ingredients == b.ingredients
+        && a.pricing == b.pricing
+}
+
+/// Every single market condition or branding level change that affects at
+/// least one candidate recipe.
+fn condition_changes(simulate_config: &SimulateConfig, recipes: &[Recipe]) -> Vec<ConditionChange> {
+    let conditions = [
+        MarketCondition::HighDemand,
+        MarketCondition::InDemand,
+        MarketCondition::LowDemand,
+        MarketCondition::Trendy,
+    ];
+
+    let mut changes = Vec::new();
+    for (potion_kind_key, _) in POTION_KINDS.iter() {
+        if !recipes
+            .iter()
+            .any(|recipe| recipe.potion_kind_key == *potion_kind_key)
+        {
+            continue;
+        }
+        let current = simulate_config.market_conditions.get(potion_kind_key);
+        for condition in conditions.iter() {
+            let present = current.is_some_and(|current| current.contains(condition));
+            changes.push(if present {
+                ConditionChange::RemoveMarket(potion_kind_key.clone(), condition.clone())
+            } else {
+                ConditionChange::AddMarket(potion_kind_key.clone(), condition.clone())
+            });
+        }
+    }
+
+    let categories = [
+        BrandingCategory::Bulk,
+        BrandingCategory::Health,
+        BrandingCategory::Sourcery,
+        BrandingCategory::Provisions,
+    ];
+    for category in categories.iter() {
+        // Bulk branding cannot be weighed without prices.
+        if *category == BrandingCategory::Bulk && simulate_config.price_model.is_none() {
+            continue;
+        }
+        changes.push(ConditionChange::Branding(category.clone(), 1));
+        if *simulate_config.branding_counts.get(category).unwrap_or(&0) > 0 {
+            changes.push(ConditionChange::Branding(category.clone(), -1));
+        }
+    }
+    changes
+}
+
+fn apply_change(simulate_config: &SimulateConfig, change: &ConditionChange) -> SimulateConfig {
+    let mut changed = simulate_config.clone();
+    match change {
+        ConditionChange::AddMarket(potion_kind_key, condition) => {
+            changed
+                .market_conditions
+                .entry(potion_kind_key.clone())
+                .or_default()
+                .push(condition.clone());
+        }
+        ConditionChange::RemoveMarket(potion_kind_key, condition) => {
+            if let Some(conditions) = changed.market_conditions.get_mut(potion_kind_key) {
+                if let Some(position) = conditions.iter().position(|other| other == condition) {
+                    conditions.remove(position);
+                }
+            }
+        }
+        ConditionChange::Branding(category, delta) => {
+            *changed.branding_counts.entry(category.clone()).or_insert(0) += delta;
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        core::enumerate,
+        models::{traits::GetByKey, IngredientKey, IngredientProcess, Process, INGREDIENTS},
+        process::PROCESS_VARIANTS,
+        recommend::MarketConditions,
+        simulate::{analyse, simulate},
+        testdata::{enumerate_config, INGREDIENT_COMBINATIONS, TEST_INGREDIENTS},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_analyse_sensitivity() {
+        let ingredients = TEST_INGREDIENTS.to_vec();
+        let enumerate_config = enumerate_config(
+            &ingredients,
+            3,
+            vec![Process::Crush, Process::Dry, Process::Blanch],
+        );
+        let intrinsics =
+            enumerate(&enumerate_config, &NoProgress, &CancellationToken::new()).unwrap();
+
+        let mut market_conditions = MarketConditions::new();
+        market_conditions.insert(PotionKindKey::Mana, vec![MarketCondition::HighDemand]);
+        let simulate_config = SimulateConfig {
+            market_conditions,
+            ..SimulateConfig::default()
+        };
+        let config = RecommendConfig {
+            available_ingredients: ingredients.iter().map(|&key| (key, 1)).collect(),
+            utilisation: 3,
+            potions: vec![],
+        };
+
+        let cancel = CancellationToken::new();
+        let (possible_recipes, _) = prune_dominated(score_recipes(
+            &intrinsics,
+            &simulate_config,
+            &enumerate_config.filters,
+        ));
+        let portfolio = recommend(possible_recipes, &config, &NoProgress, &cancel).unwrap();
+        let report = analyse_sensitivity(
+            &intrinsics,
+            &simulate_config,
+            &enumerate_config.filters,
+            &config,
+            &portfolio,
+            5,
+            &cancel,
+        )
+        .unwrap();
+
+        assert_eq!(report.selected.len(), portfolio.len());
+        assert!(report.unselected.len() <= 5);
+
+        // The portfolio is optimal, so no recipe can be swapped in or out for
+        // free on appeal.
+        for sensitivity in report.selected.iter().chain(report.unselected.iter()) {
+            if let Some(margin) = sensitivity.appeal_margin {
+                assert!(margin >= 0);
+            }
+        }
+
+        for flip in &report.flips {
+            assert!(!flip.entering.is_empty() || !flip.leaving.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_margins_include_dominated_alternatives() {
+        let simulate_config = SimulateConfig::default();
+        // Speed, Slow and Mana: one recipe for each department.
+        let portfolio: Vec<Recipe> = INGREDIENT_COMBINATIONS[12..15]
+            .iter()
+            .map(|(ingredients, _)| simulate(ingredients, &simulate_config).unwrap())
+            .collect();
+        let mana = &portfolio[2];
+        assert_eq!(mana.potion_kind_key, PotionKindKey::Mana);
+
+        // The only other Sourcery recipe uses more ingredients for less
+        // appeal, so Mana dominates it.
+        let thyme = INGREDIENTS.get_by_key(&IngredientKey::Thyme).clone();
+        let mut runner_up = mana.clone();
+        runner_up.ingredients.push(thyme.clone());
+        runner_up.alternatives.push(vec![thyme.process.clone()]);
+        runner_up.overall_appeal -= 7;
+
+        let mut recipes = portfolio.clone();
+        recipes.push(runner_up.clone());
+        let (pruned_recipes, pruned) = prune_dominated(recipes.clone());
+        assert_eq!(pruned, 1);
+        assert!(!pruned_recipes.contains(&runner_up));
+
+        let config = RecommendConfig {
+            available_ingredients: [
+                IngredientKey::Catnip,
+                IngredientKey::Lupine,
+                IngredientKey::Sage,
+                IngredientKey::Wizards,
+                IngredientKey::Thyme,
+            ]
+            .iter()
+            .map(|&key| (key, 1))
+            .collect(),
+            utilisation: 3,
+            potions: vec![],
+        };
+        let cancel = CancellationToken::new();
+
+        let (selected, unselected) =
+            recipe_margins(&recipes, &config, &portfolio, 5, &cancel).unwrap();
+        assert_eq!(selected.len(), portfolio.len());
+        assert_eq!(selected[2].appeal_margin, Some(7));
+        assert_eq!(unselected.len(), 1);
+        assert_eq!(unselected[0].recipe, runner_up);
+        assert_eq!(unselected[0].appeal_margin, Some(7));
+
+        // A portfolio recipe missing from the candidates is still reported.
+        let (selected, _) = recipe_margins(&recipes[..2], &config, &portfolio, 5, &cancel).unwrap();
+        assert_eq!(selected.len(), portfolio.len());
+        assert_eq!(selected[2].appeal_margin, None);
+        assert_eq!(selected[2].potency_margin, None);
+    }
+
+    #[test]
+    fn test_tied_portfolio_is_not_a_flip() {
+        let variant = |key, process| PROCESS_VARIANTS.get(&key, &process).unwrap().clone();
+        // Speed and Slow fill the other departments. The two Summoning
+        // recipes share Flyagaric and have the same appeal and potency.
+        let intrinsics: Vec<RecipeIntrinsics> = [
+            INGREDIENT_COMBINATIONS[12].0.clone(),
+            INGREDIENT_COMBINATIONS[13].0.clone(),
+            vec![
+                variant(IngredientKey::Flyagaric, IngredientProcess::Raw),
+                variant(IngredientKey::Wizards, IngredientProcess::Crushed),
+            ],
+            vec![
+                variant(IngredientKey::Flyagaric, IngredientProcess::Crushed),
+                variant(IngredientKey::Pluteus, IngredientProcess::Pickled),
+            ],
+        ]
+        .iter()
+        .map(|ingredients| analyse(ingredients).unwrap())
+        .collect();
+
+        let simulate_config = SimulateConfig::default();
+        let config = RecommendConfig {
+            available_ingredients: [
+                (IngredientKey::Catnip, 2),
+                (IngredientKey::Lupine, 1),
+                (IngredientKey::Sage, 1),
+                (IngredientKey::Flyagaric, 1),
+                (IngredientKey::Wizards, 1),
+                (IngredientKey::Pluteus, 1),
+            ]
+            .into_iter()
+            .collect(),
+            utilisation: 1,
+            potions: vec![],
+        };
+        let cancel = CancellationToken::new();
+
+        let recipes = score_recipes(&intrinsics, &simulate_config, &[]);
+        assert_eq!(recipes.len(), 4);
+        assert_eq!(recipes[2].overall_appeal, recipes[3].overall_appeal);
+        assert_eq!(recipes[2].overall_potency, recipes[3].overall_potency);
+
+        // Hand in the Summoning recipe the solver does not pick, which is just
+        // as good.
+        let chosen = recommend(recipes.clone(), &config, &NoProgress, &cancel).unwrap();
+        assert_eq!(chosen.len(), 3);
+        let portfolio: Vec<Recipe> = recipes
+            .iter()
+            .filter(|recipe| {
+                (recipe.potion_kind_key == PotionKindKey::Summoning)
+                    != chosen.iter().any(|other| same_offer(recipe, other))
+            })
+            .cloned()
+            .collect();
+        assert_eq!(portfolio.len(), 3);
+
+        let report = analyse_sensitivity(
+            &intrinsics,
+            &simulate_config,
+            &[],
+            &config,
+            &portfolio,
+            5,
+            &cancel,
+        )
+        .unwrap();
+
+        // Health branding leaves both Summoning recipes unchanged.
+        let unchanged = ConditionChange::Branding(BrandingCategory::Health, 1);
+        assert!(condition_changes(&simulate_config, &recipes).contains(&unchanged));
+        assert!(!report.flips.iter().any(|flip| flip.change == unchanged));
+    }
+}
@@ -1,22 +1,57 @@
+use std::{
+    collections::HashMap,
+    hash::{BuildHasherDefault, Hasher},
+    iter::Sum,
+    ops::Add,
+    sync::RwLock,
+};
+
+use serde::Deserialize;
+
 use crate::{
     models::{
         self,
+        traits::GetByKey,
         AlchemistAttribute::{self, Acclaimed},
         AppealLookup, AppealMapNegative, AppealMapPositive,
         BrandingCategory::{Bulk, Health, Provisions, Sourcery},
         Department, Element, GetByParts, Ingredient, IngredientKind, IngredientPart,
         IngredientParts, MainEffect, MarketCondition, OverallPurity, OverallTaste, OverallToxicity,
-        PotionKind, Recipe, Sweetness, Taste, TasteEffect, Tastiness, ToxicityEffect,
-        ValidCombination,
+        PotionKind, Pricing, Recipe, RecipeIntrinsics, Sweetness, Taste, TasteEffect, Tastiness,
+        ToxicityEffect, ValidCombination,
     },
-    recommend::{AlchemistAttributes, BrandingCounts, MarketConditions},
+    recommend::{AlchemistAttributes, AlchemistRoster, BrandingCounts, MarketConditions},
 };
 
-#[derive(Debug)]
+/// The market, alchemists and branding recipes are scored against. The
+/// default is a quiet market without alchemists, branding or prices.
+#[derive(Debug, Clone, Default)]
 pub struct SimulateConfig {
-    pub alchemists_attributes: AlchemistAttributes,
+    pub alchemists: AlchemistRoster,
     pub market_conditions: MarketConditions,
     pub branding_counts: BrandingCounts,
+    pub price_model: Option<PriceModel>,
+}
+
+/// Sale price of a potion, used to weigh the appeal gained from bulk pricing
+/// against the revenue lost.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PriceModel {
+    /// Price per point of potency at standard pricing.
+    pub price_per_potency: f64,
+    /// Fraction of the standard price charged at bulk pricing.
+    pub bulk_price_factor: f64,
+}
+
+impl PriceModel {
+    pub fn price(&self, potency: i32, pricing: Pricing) -> i32 {
+        let standard_price = potency as f64 * self.price_per_potency;
+        match pricing {
+            Pricing::Standard => standard_price.round() as i32,
+            Pricing::Bulk => (standard_price * self.bulk_price_factor).round() as i32,
+        }
+    }
 }
 
 pub fn collect_parts(ingredients: &[Ingredient]) -> Vec<IngredientPart> {
@@ -143,9 +178,15 @@ pub fn determine_overall_taste(parts: &Vec<IngredientPart>) -> OverallTaste {
         }
     }
 
+    taste_from_balance(tastiness, sweetness)
+}
+
+/// Overall taste from the number of tasty minus unsavory parts and sweet
+/// minus bitter parts.
+fn taste_from_balance(tastiness: i32, sweetness: i32) -> OverallTaste {
     // Normalize
-    tastiness = tastiness.signum();
-    sweetness = sweetness.signum();
+    let tastiness = tastiness.signum();
+    let sweetness = sweetness.signum();
 
     // Map to Option<Taste> enum
     let final_tastiness: Option<Tastiness> = match tastiness {
@@ -192,6 +233,11 @@ fn determine_overall_toxicity(parts: &Vec<IngredientPart>) -> OverallToxicity {
         })
         .sum();
 
+    toxicity_from_balance(toxicity)
+}
+
+/// Overall toxicity from the number of toxin minus antitoxin parts.
+fn toxicity_from_balance(toxicity: i32) -> OverallToxicity {
     if toxicity >= 2 {
         OverallToxicity::VeryToxic
     } else if toxicity == 1 {
@@ -279,16 +325,13 @@ fn determine_element_potency(element: &Element, parts: &Vec<IngredientPart>) ->
 
 fn determine_branding_appeal(
     potion_kind: &PotionKind,
-    potency: i32,
+    pricing: Pricing,
     branding_counts: &BrandingCounts,
 ) -> i32 {
     // Bulk appeal only applies if the potion is offered with bulk pricing.
-    // As a rule of thumb, bulk pricing only makes sense if potency is below
-    // around 400.
-    let bulk_appeal: i32 = if potency < 400 {
-        *branding_counts.get(&Bulk).unwrap_or(&0)
-    } else {
-        0
+    let bulk_appeal: i32 = match pricing {
+        Pricing::Bulk => *branding_counts.get(&Bulk).unwrap_or(&0),
+        Pricing::Standard => 0,
     };
 
     let department_appeal = match potion_kind.department {
@@ -320,52 +363,84 @@ fn determine_market_appeal(potion_kind: &PotionKind, market_conditions: &MarketC
         .sum()
 }
 
+/// Count the attributes of the alchemists working in a department.
+fn department_attributes(
+    alchemists: &AlchemistRoster,
+    department: Department,
+) -> AlchemistAttributes {
+    let mut attribute_counts = AlchemistAttributes::new();
+    for alchemist in alchemists {
+        if !alchemist.departments.contains(&department) {
+            continue;
+        }
+        for attribute in &alchemist.attributes {
+            *attribute_counts.entry(attribute.clone()).or_insert(0) += 1;
+        }
+    }
+    attribute_counts
+}
+
 fn determine_alchemist_appeal(attribute_counts: &AlchemistAttributes) -> i32 {
     attribute_counts.get(&Acclaimed).unwrap_or(&0) * 3
 }
 
 fn determine_alchemist_potency(
-    alchemists_attributes: &AlchemistAttributes,
-    ingredients: &[Ingredient],
-    parts: &[IngredientPart],
+    attribute_counts: &AlchemistAttributes,
+    intrinsics: &RecipeIntrinsics,
 ) -> i32 {
-    let mut stimulant_count: i32 = 0;
-    let mut herb_count: i32 = 0;
-    let mut mushroom_count: i32 = 0;
+    let count = |attribute: AlchemistAttribute| *attribute_counts.get(&attribute).unwrap_or(&0);
 
-    for ingredient in ingredients {
-        match ingredient.kind {
-            IngredientKind::Herb => herb_count += 1,
-            IngredientKind::Mushroom => mushroom_count += 1,
-        }
-    }
+    (intrinsics.stimulant_count * count(AlchemistAttribute::Optimiser) * 10)
+        + (intrinsics.has_herb as i32 * count(AlchemistAttribute::Herbalist) * 10)
+        + (intrinsics.has_mushroom as i32 * count(AlchemistAttribute::FungiConnoisseur) * 10)
+}
 
-    for part in parts {
-        match part {
-            IngredientPart::Stimulant => stimulant_count += 1,
-            _ => {}
-        }
-    }
+/// Determine the market independent properties of a combination of
+/// ingredients, or None if it does not produce a potion.
+pub fn analyse(ingredients: &[Ingredient]) -> Option<RecipeIntrinsics> {
+    let parts = collect_parts(ingredients);
+    let (has_herb, has_mushroom) = ingredient_kinds(ingredients);
+    let intrinsics = analyse_parts(&parts, has_herb, has_mushroom)?;
+    Some(with_ingredients(intrinsics, ingredients.to_vec()))
+}
 
-    let optimiser_count = *alchemists_attributes
-        .get(&AlchemistAttribute::Optimiser)
-        .unwrap_or(&0);
-    let herbalist_count = *alchemists_attributes
-        .get(&AlchemistAttribute::Herbalist)
-        .unwrap_or(&0);
-    let fungi_connoisseur_count = *alchemists_attributes
-        .get(&AlchemistAttribute::FungiConnoisseur)
-        .unwrap_or(&0);
+/// Whether the ingredients include a herb, and a mushroom.
+pub(crate) fn ingredient_kinds<'a, I>(ingredients: I) -> (bool, bool)
+where
+    I: IntoIterator<Item = &'a Ingredient>,
+{
+    ingredients
+        .into_iter()
+        .fold((false, false), |(has_herb, has_mushroom), ingredient| {
+            (
+                has_herb || ingredient.kind == IngredientKind::Herb,
+                has_mushroom || ingredient.kind == IngredientKind::Mushroom,
+            )
+        })
+}
 
-    (stimulant_count * optimiser_count * 10)
-        + (herb_count.signum() * herbalist_count * 10)
-        + (mushroom_count.signum() * fungi_connoisseur_count * 10)
+/// Fill in the ingredient lists of intrinsics analysed without them, each
+/// ingredient standing only for its own process chain.
+pub(crate) fn with_ingredients(
+    mut intrinsics: RecipeIntrinsics,
+    ingredients: Vec<Ingredient>,
+) -> RecipeIntrinsics {
+    intrinsics.alternatives = ingredients
+        .iter()
+        .map(|ingredient| vec![ingredient.process.clone()])
+        .collect();
+    intrinsics.ingredients = ingredients;
+    intrinsics
 }
 
-pub fn simulate(ingredients: &[Ingredient], simulate_config: &SimulateConfig) -> Option<Recipe> {
-    let parts = collect_parts(ingredients);
-    let element: Option<Element> = find_dominant_element(&parts);
-    let main_effect: Option<MainEffect> = find_dominant_main_effect(&parts);
+/// Everything but the ingredient lists of the intrinsics, which are left empty.
+fn analyse_parts(
+    parts: &Vec<IngredientPart>,
+    has_herb: bool,
+    has_mushroom: bool,
+) -> Option<RecipeIntrinsics> {
+    let element: Option<Element> = find_dominant_element(parts);
+    let main_effect: Option<MainEffect> = find_dominant_main_effect(parts);
 
     if element.is_none() || main_effect.is_none() {
         return None;
@@ -378,54 +453,430 @@ pub fn simulate(ingredients: &[Ingredient], simulate_config: &SimulateConfig) ->
     let valid_combination = ValidCombination::new(main_effect, element).unwrap();
     let potion_kind = models::POTION_KINDS.get_by_parts(valid_combination);
 
-    let overall_purity = determine_overall_purity(&parts);
-    let overall_taste = determine_overall_taste(&parts);
-    let overall_toxicity = determine_overall_toxicity(&parts);
-
-    let purity_potency = determine_purity_potency(&parts);
-    let toxicity_potency = determine_toxicity_potency(potion_kind, &parts);
-    let element_potency = determine_element_potency(&element, &parts);
-    let main_effect_potency = determine_main_effect_potency(&main_effect, &parts);
-    let alchemist_potency =
-        determine_alchemist_potency(&simulate_config.alchemists_attributes, &ingredients, &parts);
-    let overall_potency = purity_potency
-        + toxicity_potency
-        + element_potency
-        + main_effect_potency
-        + alchemist_potency;
+    let overall_purity = determine_overall_purity(parts);
+    let overall_taste = determine_overall_taste(parts);
+    let overall_toxicity = determine_overall_toxicity(parts);
+
+    let purity_potency = determine_purity_potency(parts);
+    let toxicity_potency = determine_toxicity_potency(potion_kind, parts);
+    let element_potency = determine_element_potency(&element, parts);
+    let main_effect_potency = determine_main_effect_potency(&main_effect, parts);
+    let base_potency = purity_potency + toxicity_potency + element_potency + main_effect_potency;
 
     let purity_appeal = determine_purity_appeal(overall_purity);
     let taste_appeal = determine_taste_appeal(potion_kind, overall_taste);
     let toxicity_appeal = determine_toxicity_appeal(potion_kind, overall_toxicity);
-    let market_appeal = determine_market_appeal(potion_kind, &simulate_config.market_conditions);
-    let alchemist_appeal = determine_alchemist_appeal(&simulate_config.alchemists_attributes);
-    let branding_appeal = determine_branding_appeal(
-        potion_kind,
-        overall_potency,
-        &simulate_config.branding_counts,
+    let base_appeal = purity_appeal + taste_appeal + toxicity_appeal;
+
+    let stimulant_count = parts
+        .iter()
+        .filter(|&&part| part == IngredientPart::Stimulant)
+        .count() as i32;
+
+    Some(RecipeIntrinsics {
+        potion_kind_key: potion_kind.key.clone(),
+        ingredients: Vec::new(),
+        alternatives: Vec::new(),
+        overall_purity,
+        overall_taste,
+        overall_toxicity,
+        base_appeal,
+        base_potency,
+        stimulant_count,
+        has_herb,
+        has_mushroom,
+    })
+}
+
+/// Number of each kind of part in an ingredient or combination, so that the
+/// parts of a combination are the sum of the counts of its ingredients.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct PartCounts([u8; 16]);
+
+impl PartCounts {
+    pub fn of(ingredient: &Ingredient) -> Self {
+        let mut counts = PartCounts::default();
+        match &ingredient.parts {
+            IngredientParts::Raw(a, b, c, d)
+            | IngredientParts::Fermented(a, b, c, d)
+            | IngredientParts::Infused(a, b, c, d)
+            | IngredientParts::FermentedInfused(a, b, c, d) => {
+                counts.count(a);
+                counts.count(b);
+                counts.count(c);
+                counts.count(d);
+            }
+            IngredientParts::Crushed(a, b)
+            | IngredientParts::Blanched(a, b)
+            | IngredientParts::Dried(a, b)
+            | IngredientParts::Pickled(a, b)
+            | IngredientParts::CrushedFermented(a, b)
+            | IngredientParts::BlanchedFermented(a, b)
+            | IngredientParts::DriedFermented(a, b)
+            | IngredientParts::PickledFermented(a, b)
+            | IngredientParts::CrushedInfused(a, b)
+            | IngredientParts::BlanchedInfused(a, b)
+            | IngredientParts::DriedInfused(a, b)
+            | IngredientParts::PickledInfused(a, b)
+            | IngredientParts::CrushedFermentedInfused(a, b)
+            | IngredientParts::BlanchedFermentedInfused(a, b)
+            | IngredientParts::DriedFermentedInfused(a, b)
+            | IngredientParts::PickledFermentedInfused(a, b) => {
+                counts.count(a);
+                counts.count(b);
+            }
+        }
+        counts
+    }
+
+    pub fn of_all(ingredients: &[Ingredient]) -> Self {
+        ingredients.iter().map(PartCounts::of).sum()
+    }
+
+    pub fn get(&self, part: &IngredientPart) -> i32 {
+        self.0[Self::index(part)] as i32
+    }
+
+    fn count(&mut self, part: &IngredientPart) {
+        self.0[Self::index(part)] += 1;
+    }
+
+    fn index(part: &IngredientPart) -> usize {
+        match part {
+            IngredientPart::MainEffect(MainEffect::Cat) => 0,
+            IngredientPart::MainEffect(MainEffect::Bone) => 1,
+            IngredientPart::MainEffect(MainEffect::Soul) => 2,
+            IngredientPart::MainEffect(MainEffect::Beast) => 3,
+            IngredientPart::Element(Element::Fire) => 4,
+            IngredientPart::Element(Element::Water) => 5,
+            IngredientPart::Element(Element::Earth) => 6,
+            IngredientPart::Element(Element::Aether) => 7,
+            IngredientPart::Taste(Taste::Tastiness(Tastiness::Tasty)) => 8,
+            IngredientPart::Taste(Taste::Tastiness(Tastiness::Unsavory)) => 9,
+            IngredientPart::Taste(Taste::Sweetness(Sweetness::Sweet)) => 10,
+            IngredientPart::Taste(Taste::Sweetness(Sweetness::Bitter)) => 11,
+            IngredientPart::Stimulant => 12,
+            IngredientPart::Impurity => 13,
+            IngredientPart::Toxin => 14,
+            IngredientPart::Antitoxin => 15,
+        }
+    }
+
+    fn element(&self, element: Element) -> i32 {
+        self.get(&IngredientPart::Element(element))
+    }
+
+    fn main_effect(&self, main_effect: MainEffect) -> i32 {
+        self.get(&IngredientPart::MainEffect(main_effect))
+    }
+}
+
+impl Add for PartCounts {
+    type Output = PartCounts;
+
+    fn add(mut self, other: PartCounts) -> PartCounts {
+        for (count, other_count) in self.0.iter_mut().zip(other.0.iter()) {
+            *count += other_count;
+        }
+        self
+    }
+}
+
+impl Sum for PartCounts {
+    fn sum<I: Iterator<Item = PartCounts>>(iter: I) -> PartCounts {
+        iter.fold(PartCounts::default(), Add::add)
+    }
+}
+
+/// Same as `find_dominant_element`, from part counts.
+fn dominant_element(counts: &PartCounts) -> Option<Element> {
+    let fire = counts.element(Element::Fire);
+    let water = counts.element(Element::Water);
+    let earth = counts.element(Element::Earth);
+    let aether = counts.element(Element::Aether);
+    let half = (fire + water + earth + aether) / 2;
+
+    if fire - water > half {
+        Some(Element::Fire)
+    } else if water - fire > half {
+        Some(Element::Water)
+    } else if earth - aether > half {
+        Some(Element::Earth)
+    } else if aether - earth > half {
+        Some(Element::Aether)
+    } else {
+        None
+    }
+}
+
+/// Same as `find_dominant_main_effect`, from part counts.
+fn dominant_main_effect(counts: &PartCounts) -> Option<MainEffect> {
+    let main_effects = [
+        MainEffect::Cat,
+        MainEffect::Bone,
+        MainEffect::Soul,
+        MainEffect::Beast,
+    ];
+    let max_count = main_effects
+        .iter()
+        .map(|&main_effect| counts.main_effect(main_effect))
+        .max()
+        .unwrap();
+
+    let mut dominant = main_effects
+        .iter()
+        .filter(|&&main_effect| counts.main_effect(main_effect) == max_count);
+    match (dominant.next(), dominant.next()) {
+        (Some(&main_effect), None) => Some(main_effect),
+        _ => None,
+    }
+}
+
+/// Same as `analyse`, but from the summed part counts of the ingredients,
+/// which avoids collecting and rescanning their parts.
+pub fn analyse_counts(ingredients: &[Ingredient], counts: &PartCounts) -> Option<RecipeIntrinsics> {
+    let (has_herb, has_mushroom) = ingredient_kinds(ingredients);
+    let intrinsics = analyse_part_counts(counts, has_herb, has_mushroom)?;
+    Some(with_ingredients(intrinsics, ingredients.to_vec()))
+}
+
+/// Everything but the ingredient lists of `analyse_counts`, which are left
+/// empty, from the part counts and whether the ingredients include a herb
+/// and a mushroom.
+pub(crate) fn analyse_part_counts(
+    counts: &PartCounts,
+    has_herb: bool,
+    has_mushroom: bool,
+) -> Option<RecipeIntrinsics> {
+    let element = dominant_element(counts)?;
+    let main_effect = dominant_main_effect(counts)?;
+
+    // Both are dominant, so they always form a valid combination.
+    let valid_combination = ValidCombination::new(main_effect, element).unwrap();
+    let potion_kind = models::POTION_KINDS.get_by_parts(valid_combination);
+
+    let overall_purity = if counts.get(&IngredientPart::Impurity) > 0 {
+        OverallPurity::Impure
+    } else {
+        OverallPurity::Neutral
+    };
+    let overall_taste = taste_from_balance(
+        counts.get(&IngredientPart::Taste(Taste::Tastiness(Tastiness::Tasty)))
+            - counts.get(&IngredientPart::Taste(Taste::Tastiness(
+                Tastiness::Unsavory,
+            ))),
+        counts.get(&IngredientPart::Taste(Taste::Sweetness(Sweetness::Sweet)))
+            - counts.get(&IngredientPart::Taste(Taste::Sweetness(Sweetness::Bitter))),
     );
-    let overall_appeal = purity_appeal
-        + taste_appeal
-        + toxicity_appeal
-        + market_appeal
-        + alchemist_appeal
-        + branding_appeal;
-
-    Some(Recipe {
+    let overall_toxicity = toxicity_from_balance(
+        counts.get(&IngredientPart::Toxin) - counts.get(&IngredientPart::Antitoxin),
+    );
+
+    let purity_potency =
+        (counts.get(&IngredientPart::Stimulant) - counts.get(&IngredientPart::Impurity)) * 50;
+    let toxicity_potency = counts.get(&IngredientPart::Toxin)
+        * match potion_kind.toxicity_effect {
+            ToxicityEffect::ToxicPositive => 20,
+            ToxicityEffect::ToxicNegative => -20,
+        };
+    let element_potency = counts.element(element) * 50;
+    let main_effect_potency = counts.main_effect(main_effect) * 50;
+    let base_potency = purity_potency + toxicity_potency + element_potency + main_effect_potency;
+
+    let purity_appeal = determine_purity_appeal(overall_purity);
+    let taste_appeal = determine_taste_appeal(potion_kind, overall_taste);
+    let toxicity_appeal = determine_toxicity_appeal(potion_kind, overall_toxicity);
+    let base_appeal = purity_appeal + taste_appeal + toxicity_appeal;
+
+    Some(RecipeIntrinsics {
         potion_kind_key: potion_kind.key.clone(),
-        ingredients: ingredients.to_vec(),
-        overall_purity: overall_purity,
-        overall_taste: overall_taste,
-        overall_toxicity: overall_toxicity,
-        overall_appeal: overall_appeal,
-        overall_potency: overall_potency,
+        ingredients: Vec::new(),
+        alternatives: Vec::new(),
+        overall_purity,
+        overall_taste,
+        overall_toxicity,
+        base_appeal,
+        base_potency,
+        stimulant_count: counts.get(&IngredientPart::Stimulant),
+        has_herb,
+        has_mushroom,
     })
 }
 
+/// Parts multiset and herb and mushroom presence of a combination, which
+/// together determine its intrinsics.
+type MemoKey = (PartCounts, bool, bool);
+
+/// Shared memo of analysed combinations.
+///
+/// Many combinations of different ingredients share the same parts, so the
+/// analysis is done once per parts multiset and reused for the others.
+/// Enumeration only uses it with the `analysis-memo` feature, since analysing
+/// from part counts is usually cheaper than the lookup.
+#[derive(Debug, Default)]
+pub struct AnalysisMemo {
+    table: RwLock<HashMap<MemoKey, Option<RecipeIntrinsics>, BuildHasherDefault<MemoHasher>>>,
+}
+
+/// Multiplicative hasher for memo keys, which are much cheaper to hash than
+/// with the default SipHash and never come from untrusted input.
+#[derive(Debug, Default)]
+struct MemoHasher(u64);
+
+impl Hasher for MemoHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u64(byte as u64);
+        }
+    }
+
+    fn write_u8(&mut self, value: u8) {
+        self.write_u64(value as u64);
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.0 = (self.0.rotate_left(5) ^ value).wrapping_mul(0x517cc1b727220a95);
+    }
+
+    fn write_u128(&mut self, value: u128) {
+        self.write_u64(value as u64);
+        self.write_u64((value >> 64) as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl AnalysisMemo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of distinct parts multisets analysed so far.
+    pub fn len(&self) -> usize {
+        self.table.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Same as `analyse_counts`, reusing the memo for combinations with known
+/// parts.
+pub fn analyse_memoised(
+    ingredients: &[Ingredient],
+    counts: &PartCounts,
+    memo: &AnalysisMemo,
+) -> Option<RecipeIntrinsics> {
+    let (has_herb, has_mushroom) = ingredient_kinds(ingredients);
+    let intrinsics = analyse_part_counts_memoised(counts, has_herb, has_mushroom, memo)?;
+    Some(with_ingredients(intrinsics, ingredients.to_vec()))
+}
+
+/// Same as `analyse_part_counts`, reusing the memo for known parts.
+pub(crate) fn analyse_part_counts_memoised(
+    counts: &PartCounts,
+    has_herb: bool,
+    has_mushroom: bool,
+    memo: &AnalysisMemo,
+) -> Option<RecipeIntrinsics> {
+    let key = (*counts, has_herb, has_mushroom);
+
+    let cached = memo.table.read().unwrap().get(&key).cloned();
+    match cached {
+        Some(intrinsics) => intrinsics,
+        None => {
+            // Stored without ingredient lists, which differ between hits.
+            let intrinsics = analyse_part_counts(counts, has_herb, has_mushroom);
+            memo.table.write().unwrap().insert(key, intrinsics.clone());
+            intrinsics
+        }
+    }
+}
+
+/// Add the market, branding and alchemist effects to a recipe, sold at
+/// standard pricing. `score_offers` adds the bulk offer.
+pub fn score(intrinsics: &RecipeIntrinsics, simulate_config: &SimulateConfig) -> Recipe {
+    score_with_pricing(intrinsics, simulate_config, Pricing::Standard)
+}
+
+fn bulk_appeal(simulate_config: &SimulateConfig) -> i32 {
+    *simulate_config.branding_counts.get(&Bulk).unwrap_or(&0)
+}
+
+pub fn score_with_pricing(
+    intrinsics: &RecipeIntrinsics,
+    simulate_config: &SimulateConfig,
+    pricing: Pricing,
+) -> Recipe {
+    let potion_kind = models::POTION_KINDS.get_by_key(&intrinsics.potion_kind_key);
+
+    let attribute_counts =
+        department_attributes(&simulate_config.alchemists, potion_kind.department);
+    let alchemist_potency = determine_alchemist_potency(&attribute_counts, intrinsics);
+    let overall_potency = intrinsics.base_potency + alchemist_potency;
+
+    let market_appeal = determine_market_appeal(potion_kind, &simulate_config.market_conditions);
+    let alchemist_appeal = determine_alchemist_appeal(&attribute_counts);
+    let branding_appeal =
+        determine_branding_appeal(potion_kind, pricing, &simulate_config.branding_counts);
+    let overall_appeal =
+        intrinsics.base_appeal + market_appeal + alchemist_appeal + branding_appeal;
+
+    let revenue = simulate_config
+        .price_model
+        .as_ref()
+        .map(|price_model| price_model.price(overall_potency, pricing));
+
+    Recipe {
+        potion_kind_key: intrinsics.potion_kind_key.clone(),
+        ingredients: intrinsics.ingredients.clone(),
+        alternatives: intrinsics.alternatives.clone(),
+        overall_purity: intrinsics.overall_purity,
+        overall_taste: intrinsics.overall_taste,
+        overall_toxicity: intrinsics.overall_toxicity,
+        overall_appeal,
+        overall_potency,
+        pricing,
+        revenue,
+    }
+}
+
+/// Score a recipe at each pricing worth offering, leaving the choice to the
+/// recommendation. Bulk pricing is offered alongside standard pricing
+/// whenever it gains appeal; without a price model to give it a cost in
+/// revenue, it is always the better offer.
+pub fn score_offers(
+    intrinsics: &RecipeIntrinsics,
+    simulate_config: &SimulateConfig,
+) -> Vec<Recipe> {
+    let mut offers = vec![score(intrinsics, simulate_config)];
+    if bulk_appeal(simulate_config) > 0 {
+        offers.push(score_with_pricing(
+            intrinsics,
+            simulate_config,
+            Pricing::Bulk,
+        ));
+    }
+    offers
+}
+
+pub fn simulate(ingredients: &[Ingredient], simulate_config: &SimulateConfig) -> Option<Recipe> {
+    let intrinsics = analyse(ingredients)?;
+    Some(score(&intrinsics, simulate_config))
+}
+
 #[cfg(test)]
 mod tests {
+    use itertools::Itertools;
+
     use crate::{
-        recommend::{AlchemistAttributes, MarketConditions},
+        enumerate::permute_ingredients,
+        models::{Alchemist, IngredientKey, Process, INGREDIENTS},
+        process::PROCESS_VARIANTS,
+        recommend::MarketConditions,
         testdata::INGREDIENT_COMBINATIONS,
     };
 
@@ -434,11 +885,7 @@ mod tests {
     #[test]
     fn test_simulate() {
         for (ingredients, expected_potion) in INGREDIENT_COMBINATIONS.iter() {
-            let simulate_config = SimulateConfig {
-                alchemists_attributes: AlchemistAttributes::new(),
-                market_conditions: MarketConditions::new(),
-                branding_counts: BrandingCounts::new(),
-            };
+            let simulate_config = SimulateConfig::default();
             let result = simulate(&ingredients, &simulate_config);
             assert!(result.is_some());
 
@@ -446,4 +893,209 @@ mod tests {
             assert_eq!(recipe.potion_kind_key, *expected_potion);
         }
     }
+
+    #[test]
+    fn test_score_applies_market() {
+        let (ingredients, potion_kind_key) = &INGREDIENT_COMBINATIONS[0];
+        let intrinsics = analyse(ingredients).unwrap();
+
+        let mut market_conditions = MarketConditions::new();
+        market_conditions.insert(potion_kind_key.clone(), vec![MarketCondition::InDemand]);
+        let quiet_config = SimulateConfig::default();
+        let busy_config = SimulateConfig {
+            market_conditions,
+            ..SimulateConfig::default()
+        };
+
+        let quiet = score(&intrinsics, &quiet_config);
+        let busy = score(&intrinsics, &busy_config);
+
+        assert_eq!(quiet.overall_appeal, intrinsics.base_appeal);
+        assert_eq!(busy.overall_appeal, quiet.overall_appeal + 15);
+        assert_eq!(busy.overall_potency, quiet.overall_potency);
+        assert_eq!(Some(busy), simulate(ingredients, &busy_config));
+    }
+
+    #[test]
+    fn test_score_applies_department_alchemists() {
+        let (ingredients, potion_kind_key) = &INGREDIENT_COMBINATIONS[0];
+        let intrinsics = analyse(ingredients).unwrap();
+        let department = models::POTION_KINDS.get_by_key(potion_kind_key).department;
+        let other_department = match department {
+            Department::Health => Department::Sourcery,
+            _ => Department::Health,
+        };
+
+        let config = |departments: Vec<Department>| SimulateConfig {
+            alchemists: vec![Alchemist {
+                name: "Quinn".to_string(),
+                attributes: vec![AlchemistAttribute::Acclaimed, AlchemistAttribute::Herbalist],
+                departments,
+            }],
+            market_conditions: MarketConditions::new(),
+            branding_counts: BrandingCounts::new(),
+            price_model: None,
+        };
+
+        let working = score(&intrinsics, &config(vec![department]));
+        let elsewhere = score(&intrinsics, &config(vec![other_department]));
+
+        assert_eq!(elsewhere.overall_appeal, intrinsics.base_appeal);
+        assert_eq!(elsewhere.overall_potency, intrinsics.base_potency);
+        assert_eq!(working.overall_appeal, intrinsics.base_appeal + 3);
+        assert_eq!(working.overall_potency, intrinsics.base_potency + 10);
+    }
+
+    #[test]
+    fn test_score_offers_bulk_pricing() {
+        let (ingredients, _) = &INGREDIENT_COMBINATIONS[0];
+        let intrinsics = analyse(ingredients).unwrap();
+
+        let mut branding_counts = BrandingCounts::new();
+        branding_counts.insert(Bulk, 5);
+        let config = SimulateConfig {
+            alchemists: AlchemistRoster::new(),
+            market_conditions: MarketConditions::new(),
+            branding_counts,
+            price_model: Some(PriceModel {
+                price_per_potency: 1.0,
+                bulk_price_factor: 0.5,
+            }),
+        };
+
+        let offers = score_offers(&intrinsics, &config);
+        assert_eq!(offers.len(), 2);
+        let (standard, bulk) = (&offers[0], &offers[1]);
+        assert_eq!(standard.pricing, Pricing::Standard);
+        assert_eq!(bulk.pricing, Pricing::Bulk);
+        assert_eq!(bulk.overall_appeal, standard.overall_appeal + 5);
+        assert_eq!(standard.revenue, Some(intrinsics.base_potency));
+        assert_eq!(
+            bulk.revenue,
+            Some((intrinsics.base_potency as f64 * 0.5).round() as i32)
+        );
+
+        // Without a price model, both pricings are still offered, and bulk
+        // pricing only differs in appeal.
+        let unpriced = SimulateConfig {
+            price_model: None,
+            branding_counts: config.branding_counts.clone(),
+            ..config
+        };
+        let offers = score_offers(&intrinsics, &unpriced);
+        assert_eq!(offers.len(), 2);
+        assert_eq!(offers[0].pricing, Pricing::Standard);
+        assert_eq!(offers[1].pricing, Pricing::Bulk);
+        assert_eq!(offers[1].overall_appeal, offers[0].overall_appeal + 5);
+        assert_eq!(offers[1].overall_potency, offers[0].overall_potency);
+        assert_eq!(offers[1].revenue, None);
+
+        let no_bulk = SimulateConfig {
+            branding_counts: BrandingCounts::new(),
+            ..unpriced
+        };
+        assert_eq!(
+            score_offers(&intrinsics, &no_bulk),
+            vec![score_with_pricing(&intrinsics, &no_bulk, Pricing::Standard)]
+        );
+    }
+
+    #[test]
+    fn test_analyse_counts_matches_analyse() {
+        let raw_ingredients: Vec<_> = INGREDIENTS
+            .0
+            .iter()
+            .map(|(_, ingredient)| ingredient)
+            .collect();
+        let processes = vec![
+            Process::Crush,
+            Process::Blanch,
+            Process::Dry,
+            Process::Pickle,
+            Process::Ferment,
+            Process::Infuse,
+        ];
+        let all_ingredients =
+            permute_ingredients(&PROCESS_VARIANTS, raw_ingredients.as_slice(), &processes);
+
+        let check = |combination: &[Ingredient]| {
+            let counts = PartCounts::of_all(combination);
+            let intrinsics = analyse(combination);
+            assert_eq!(
+                analyse_counts(combination, &counts),
+                intrinsics,
+                "{:?}",
+                combination
+            );
+            intrinsics.is_some()
+        };
+
+        for k in 1..=3 {
+            for combination in all_ingredients.iter().cloned().combinations(k) {
+                check(&combination);
+            }
+        }
+
+        // Every combination of four or five of all the variants is too many.
+        // Instead, check every combination of four or five, repeats included,
+        // of the variants of five ingredients that between them carry every
+        // part of the catalogue and both kinds of ingredient.
+        let reduced_keys = [
+            IngredientKey::Thyme,
+            IngredientKey::Mandrake,
+            IngredientKey::Wormwood,
+            IngredientKey::Stinkhorn,
+            IngredientKey::Flyagaric,
+        ];
+        let reduced: Vec<Ingredient> = all_ingredients
+            .iter()
+            .filter(|ingredient| reduced_keys.contains(&ingredient.key))
+            .cloned()
+            .collect();
+        let parts_of = |ingredients: &[Ingredient]| {
+            let mut parts = collect_parts(ingredients);
+            parts.sort();
+            parts.dedup();
+            parts
+        };
+        assert_eq!(parts_of(&reduced), parts_of(&all_ingredients));
+        assert_eq!(ingredient_kinds(&reduced), (true, true));
+        assert_eq!(reduced.len(), 32);
+
+        for k in 4..=5 {
+            let mut potions = 0;
+            for combination in reduced.iter().cloned().combinations_with_replacement(k) {
+                if check(&combination) {
+                    potions += 1;
+                }
+            }
+            assert!(potions > 0);
+        }
+    }
+
+    #[test]
+    fn test_analyse_memoised() {
+        let memo = AnalysisMemo::new();
+        for _ in 0..2 {
+            for (ingredients, _) in INGREDIENT_COMBINATIONS.iter() {
+                let counts = PartCounts::of_all(ingredients);
+                assert_eq!(
+                    analyse_memoised(ingredients, &counts, &memo),
+                    analyse(ingredients)
+                );
+            }
+        }
+        assert!(memo.len() <= INGREDIENT_COMBINATIONS.len());
+
+        // Same parts in a different order share an entry.
+        let (ingredients, _) = &INGREDIENT_COMBINATIONS[0];
+        let reversed: Vec<Ingredient> = ingredients.iter().rev().cloned().collect();
+        let entries = memo.len();
+        let counts = PartCounts::of_all(&reversed);
+        assert_eq!(
+            analyse_memoised(&reversed, &counts, &memo),
+            analyse(&reversed)
+        );
+        assert_eq!(memo.len(), entries);
+    }
 }
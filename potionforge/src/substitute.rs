@@ -0,0 +1,279 @@
+use std::{cmp::Reverse, collections::HashMap, sync::Arc};
+
+use itertools::Itertools;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    core::{
+        expand_alternatives, is_combination_reasonable, is_combination_valid,
+        is_intrinsics_reasonable, is_recipe_reasonable,
+    },
+    enumerate::{collapse_equivalent_ingredients, permute_ingredients, CandidateFilter},
+    models::{
+        traits::GetByKey, Ingredient, IngredientKey, IngredientProcess, Process, Recipe,
+        INGREDIENTS,
+    },
+    process::VariantTable,
+    simulate::{analyse_counts, score_offers, PartCounts, SimulateConfig},
+};
+
+#[derive(Debug, Clone)]
+pub struct SubstituteConfig {
+    /// Ingredients that may be brought into the recipe.
+    pub ingredients: Vec<IngredientKey>,
+    pub processes: Vec<Process>,
+    /// Most ingredients of the recipe to replace at once.
+    pub max_swaps: usize,
+    /// Allow a substitute to use an ingredient the recipe keeps.
+    pub repeated_ingredients: bool,
+    pub filters: Vec<CandidateFilter>,
+    /// Processed variants of the catalogue under the rules in use.
+    pub variants: Arc<VariantTable>,
+}
+
+/// A recipe for the same potion kind with some ingredients replaced.
+#[derive(Debug, Clone)]
+pub struct Substitution {
+    pub recipe: Recipe,
+    /// Ingredients of the original recipe that were replaced.
+    pub replaced: Vec<Ingredient>,
+    /// Appeal of the substitute less that of the original.
+    pub appeal_difference: i32,
+    /// Potency of the substitute less that of the original.
+    pub potency_difference: i32,
+}
+
+/// Find replacements for a recipe when some of its ingredients are
+/// unavailable, closest first.
+///
+/// Every unavailable ingredient of the recipe is replaced, along with other
+/// ingredients up to `max_swaps` in total, by any processed variant of the
+/// configured ingredients. Substitutes must brew the same potion kind and
+/// pass the same filters as enumeration. They are scored at the pricing of
+/// the original when offered, and ranked by how far their appeal and then
+/// potency are from the original, either way. Among equally close
+/// substitutes, better ones come first.
+pub fn suggest_substitutions(
+    recipe: &Recipe,
+    unavailable: &[IngredientKey],
+    simulate_config: &SimulateConfig,
+    config: &SubstituteConfig,
+) -> Vec<Substitution> {
+    let required: Vec<usize> = recipe
+        .ingredients
+        .iter()
+        .positions(|ingredient| unavailable.contains(&ingredient.key))
+        .collect();
+    if required.len() > config.max_swaps {
+        return Vec::new();
+    }
+
+    let raw_ingredients: Vec<_> = config
+        .ingredients
+        .iter()
+        .filter(|key| !unavailable.contains(key))
+        .map(|key| INGREDIENTS.get_by_key(key))
+        .collect();
+    let classes = collapse_equivalent_ingredients(
+        &config.variants,
+        permute_ingredients(
+            &config.variants,
+            raw_ingredients.as_slice(),
+            &config.processes,
+        ),
+    );
+    let mut alternatives: HashMap<Ingredient, Vec<IngredientProcess>> = recipe
+        .ingredients
+        .iter()
+        .cloned()
+        .zip(recipe.alternatives.iter().cloned())
+        .collect();
+    let variants: Vec<Ingredient> = classes
+        .into_iter()
+        .map(|class| {
+            alternatives
+                .entry(class.representative.clone())
+                .or_insert(class.processes);
+            class.representative
+        })
+        .collect();
+
+    // Which ingredients to replace: the unavailable ones and up to
+    // `max_swaps` in total.
+    let optional: Vec<usize> = (0..recipe.ingredients.len())
+        .filter(|i| !required.contains(i))
+        .collect();
+    let replacements: Vec<Vec<usize>> = (0..=config.max_swaps - required.len())
+        .flat_map(|extra| optional.iter().cloned().combinations(extra))
+        .map(|extra| required.iter().cloned().chain(extra).collect::<Vec<_>>())
+        .filter(|replaced| !replaced.is_empty())
+        .collect();
+
+    let mut substitutions: Vec<Substitution> = replacements
+        .into_par_iter()
+        .flat_map_iter(|replaced| {
+            let kept: Vec<Ingredient> = recipe
+                .ingredients
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| !replaced.contains(i))
+                .map(|(_, ingredient)| ingredient.clone())
+                .collect();
+            let replaced: Vec<Ingredient> = replaced
+                .iter()
+                .map(|&i| recipe.ingredients[i].clone())
+                .collect();
+            // An ingredient may come back with a different process chain.
+            let candidates: Vec<&Ingredient> = variants
+                .iter()
+                .filter(|variant| !replaced.contains(variant))
+                .filter(|variant| {
+                    config.repeated_ingredients
+                        || !kept.iter().any(|ingredient| ingredient.key == variant.key)
+                })
+                .collect();
+
+            candidates
+                .into_iter()
+                .combinations(replaced.len())
+                .filter_map(|added| {
+                    let ingredients: Vec<Ingredient> = kept
+                        .iter()
+                        .cloned()
+                        .chain(added.into_iter().cloned())
+                        .collect();
+                    let substitute = score_substitute(
+                        ingredients,
+                        recipe,
+                        simulate_config,
+                        &alternatives,
+                        config,
+                    )?;
+                    Some(Substitution {
+                        appeal_difference: substitute.overall_appeal - recipe.overall_appeal,
+                        potency_difference: substitute.overall_potency - recipe.overall_potency,
+                        recipe: substitute,
+                        replaced: replaced.clone(),
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    substitutions.sort_by_key(|substitution| {
+        (
+            substitution.appeal_difference.abs(),
+            substitution.potency_difference.abs(),
+            Reverse(substitution.appeal_difference),
+            Reverse(substitution.potency_difference),
+        )
+    });
+    substitutions
+}
+
+/// Score the ingredients as a substitute for the recipe, if they brew the
+/// same potion kind and would survive enumeration and scoring.
+fn score_substitute(
+    ingredients: Vec<Ingredient>,
+    recipe: &Recipe,
+    simulate_config: &SimulateConfig,
+    alternatives: &HashMap<Ingredient, Vec<IngredientProcess>>,
+    config: &SubstituteConfig,
+) -> Option<Recipe> {
+    if !is_combination_valid(&ingredients, config.repeated_ingredients) {
+        return None;
+    }
+    let counts = PartCounts::of_all(&ingredients);
+    if !is_combination_reasonable(&counts, &config.filters) {
+        return None;
+    }
+    let intrinsics = analyse_counts(&ingredients, &counts)?;
+    if intrinsics.potion_kind_key != recipe.potion_kind_key
+        || !is_intrinsics_reasonable(&intrinsics, &config.filters)
+    {
+        return None;
+    }
+    let intrinsics = expand_alternatives(intrinsics, alternatives);
+
+    let offers = score_offers(&intrinsics, simulate_config);
+    let substitute = match offers.iter().find(|offer| offer.pricing == recipe.pricing) {
+        Some(offer) => offer.clone(),
+        None => offers[0].clone(),
+    };
+    if !is_recipe_reasonable(&substitute, &config.filters) {
+        return None;
+    }
+    Some(substitute)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        core::{enumerate_and_simulate, prune_dominated},
+        enumerate::default_filters,
+        process::PROCESS_VARIANTS,
+        progress::{CancellationToken, NoProgress},
+        testdata::{enumerate_config, TEST_INGREDIENTS},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_suggest_substitutions() {
+        let ingredients = TEST_INGREDIENTS.to_vec();
+        let processes = vec![Process::Crush, Process::Dry, Process::Blanch];
+        let enumerate_config = enumerate_config(&ingredients, 3, processes.clone());
+        let simulate_config = SimulateConfig::default();
+        let (recipes, _) = prune_dominated(
+            enumerate_and_simulate(
+                &enumerate_config,
+                &simulate_config,
+                &NoProgress,
+                &CancellationToken::new(),
+            )
+            .unwrap(),
+        );
+        let config = SubstituteConfig {
+            ingredients,
+            processes,
+            max_swaps: 2,
+            repeated_ingredients: false,
+            filters: default_filters(),
+            variants: PROCESS_VARIANTS.clone(),
+        };
+
+        let recipe = recipes
+            .iter()
+            .find(|recipe| recipe.ingredients.len() == 3)
+            .unwrap();
+        let unavailable = vec![recipe.ingredients[0].key];
+        let substitutions = suggest_substitutions(recipe, &unavailable, &simulate_config, &config);
+
+        assert!(!substitutions.is_empty());
+        for substitution in &substitutions {
+            assert_eq!(substitution.recipe.potion_kind_key, recipe.potion_kind_key);
+            assert!(substitution
+                .recipe
+                .ingredients
+                .iter()
+                .all(|ingredient| ingredient.key != unavailable[0]));
+            assert!(substitution
+                .replaced
+                .iter()
+                .any(|ingredient| ingredient.key == unavailable[0]));
+        }
+        assert!(substitutions.windows(2).all(|pair| {
+            (
+                pair[0].appeal_difference.abs(),
+                pair[0].potency_difference.abs(),
+            ) <= (
+                pair[1].appeal_difference.abs(),
+                pair[1].potency_difference.abs(),
+            )
+        }));
+
+        // Too many unavailable ingredients to replace.
+        let keys: Vec<IngredientKey> = recipe.ingredients.iter().map(|i| i.key).collect();
+        assert!(suggest_substitutions(recipe, &keys, &simulate_config, &config).is_empty());
+    }
+}
@@ -1,4 +1,44 @@
-use crate::models::{traits::GetByKey, Ingredient, IngredientKey, PotionKindKey, INGREDIENTS};
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    enumerate::{default_filters, EnumerateConfig},
+    models::{traits::GetByKey, Ingredient, IngredientKey, PotionKindKey, Process, INGREDIENTS},
+    process::PROCESS_VARIANTS,
+};
+
+/// Ingredients making several potion kinds in each department, few enough to
+/// enumerate and recommend quickly.
+pub const TEST_INGREDIENTS: [IngredientKey; 7] = [
+    IngredientKey::Pluteus,
+    IngredientKey::Thyme,
+    IngredientKey::Sage,
+    IngredientKey::Catnip,
+    IngredientKey::Lupine,
+    IngredientKey::Flyagaric,
+    IngredientKey::Wizards,
+];
+
+/// Enumerate the ingredients under the current rules with the default
+/// filters, a utilisation of three and no repeated ingredients.
+pub fn enumerate_config(
+    ingredients: &[IngredientKey],
+    arcane_power: i64,
+    processes: Vec<Process>,
+) -> EnumerateConfig {
+    EnumerateConfig {
+        ingredients: ingredients.to_vec(),
+        arcane_power,
+        utilisation: 3,
+        processes,
+        repeated_ingredients: false,
+        filters: default_filters(),
+        variants: PROCESS_VARIANTS.clone(),
+    }
+}
 
 lazy_static::lazy_static! {
     pub static ref INGREDIENT_COMBINATIONS: Vec<(Vec<Ingredient>, PotionKindKey)> = vec![
@@ -84,3 +124,20 @@ lazy_static::lazy_static! {
         ),
     ];
 }
+
+/// A fresh directory path under the system temporary directory, distinct for
+/// every call and every process so concurrent test runs do not collide.
+pub fn unique_temp_dir(prefix: &str) -> PathBuf {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.subsec_nanos())
+        .unwrap_or(0);
+    std::env::temp_dir().join(format!(
+        "{}-{}-{}-{}",
+        prefix,
+        std::process::id(),
+        CALLS.fetch_add(1, Ordering::Relaxed),
+        nanos
+    ))
+}
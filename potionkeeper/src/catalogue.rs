@@ -0,0 +1,41 @@
+use std::{error::Error, sync::Arc};
+
+use potionforge::{
+    models::{
+        traits::{GetName, ToHumanReadable},
+        Ingredient, IngredientKey,
+    },
+    process::{ProcessRules, VariantTable, PROCESS_VARIANTS},
+};
+
+/// Processed variants under the rules in the file, or under the built in
+/// rules.
+pub fn load_variants(rules: Option<String>) -> Result<Arc<VariantTable>, Box<dyn Error>> {
+    Ok(match rules {
+        Some(path) => Arc::new(VariantTable::from_rules(&ProcessRules::load(path)?)),
+        None => PROCESS_VARIANTS.clone(),
+    })
+}
+
+/// List every processed variant of the ingredient, or of every ingredient.
+pub fn catalogue(
+    ingredient: Option<IngredientKey>,
+    rules: Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    let table = load_variants(rules)?;
+    let variants: Vec<&Ingredient> = match ingredient {
+        Some(key) => table.variants(&key).collect(),
+        None => table.iter().collect(),
+    };
+
+    for ingredient in variants {
+        println!(
+            "{} ({}): {}",
+            ingredient.name(),
+            ingredient.process.to_human(),
+            ingredient.parts.to_human()
+        );
+    }
+
+    Ok(())
+}
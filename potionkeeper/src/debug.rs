@@ -1,18 +1,119 @@
-use std::{collections::HashMap, fs::File, io::Read};
+use std::{collections::HashMap, error::Error, fs::File, io::Read, sync::Arc};
 
 use potionforge::{
-    models::{traits::GetByKey, Ingredient, IngredientKey, Process, Recipe, INGREDIENTS},
-    process::process_ingredient,
-    recommend::{AlchemistAttributes, BrandingCounts, MarketConditions},
+    models::{
+        traits::{GetByKey, GetName, ToHumanReadable},
+        Ingredient, IngredientKey, IngredientPart, Process, Recipe, INGREDIENTS, POTION_KINDS,
+    },
+    process::{
+        ProcessRules, ProcessTrace, StepOutcome, VariantTable, ALL_PROCESSES, PROCESS_VARIANTS,
+    },
+    repair::{suggest_repairs, Edit, RepairConfig},
     simulate::{simulate, SimulateConfig},
 };
 use serde::Deserialize;
 
 use crate::printer;
 
+const REPAIR_LIMIT: usize = 5;
+
 #[derive(Debug, Deserialize)]
 struct Config {
     recipes: Vec<HashMap<IngredientKey, Vec<Process>>>,
+    /// Process rules of a newer game release, replacing the built in ones.
+    #[serde(default)]
+    process_rules: Option<ProcessRules>,
+}
+
+fn process_recipe(
+    variants: &VariantTable,
+    recipe: &HashMap<IngredientKey, Vec<Process>>,
+) -> Vec<Ingredient> {
+    recipe
+        .iter()
+        .map(|(ingredient_key, ingredient_process)| {
+            variants.process(ingredient_key, ingredient_process).clone()
+        })
+        .collect()
+}
+
+/// Ingredients of each recipe listed in a debug configuration file, processed
+/// under the given rules.
+pub fn load_recipes(
+    config_filename: &str,
+    variants: &VariantTable,
+) -> Result<Vec<Vec<Ingredient>>, Box<dyn Error>> {
+    let mut config_file = File::open(config_filename)?;
+    let mut config_contents = String::new();
+    config_file.read_to_string(&mut config_contents)?;
+
+    let config: Config = serde_yaml::from_str(&config_contents)?;
+    Ok(config
+        .recipes
+        .iter()
+        .map(|recipe| process_recipe(variants, recipe))
+        .collect())
+}
+
+fn display_parts(parts: &[IngredientPart]) -> String {
+    let parts: Vec<String> = parts.iter().map(|part| part.to_human()).collect();
+    parts.join(", ")
+}
+
+fn display_trace(trace: &ProcessTrace) {
+    println!("Processing {}", trace.result.name());
+    for step in &trace.steps {
+        let outcome = match &step.outcome {
+            StepOutcome::Cut { dropped } => format!("dropped {}", display_parts(dropped)),
+            StepOutcome::Fermented { replaced } | StepOutcome::Infused { swapped: replaced } => {
+                let changes: Vec<String> = replaced
+                    .iter()
+                    .map(|(from, to)| format!("{} to {}", from.to_human(), to.to_human()))
+                    .collect();
+                format!("changed {}", changes.join(", "))
+            }
+            StepOutcome::Skipped(reason) => format!("skipped: {:?}", reason),
+        };
+        println!(
+            "  {:?}: ({}) -> ({}), {}",
+            step.process,
+            display_parts(&step.before),
+            display_parts(&step.after),
+            outcome
+        );
+    }
+}
+
+fn display_repairs(
+    ingredients: &[Ingredient],
+    simulate_config: &SimulateConfig,
+    repair_config: &RepairConfig,
+) {
+    let names: Vec<String> = ingredients.iter().map(|i| i.to_human()).collect();
+    println!("No potion from {}", names.join(", "));
+
+    let repairs = suggest_repairs(ingredients, simulate_config, repair_config);
+    if repairs.is_empty() {
+        println!("  No single change makes a potion");
+    }
+    for repair in repairs.iter().take(REPAIR_LIMIT) {
+        let edit = match &repair.edit {
+            Edit::Add(added) => format!("Add {}", added.to_human()),
+            Edit::Remove(removed) => format!("Remove {}", removed.to_human()),
+            Edit::Reprocess { from, to } => {
+                format!("Use {} instead of {}", to.to_human(), from.to_human())
+            }
+        };
+        println!(
+            "  {}: {} with appeal {} and potency {}",
+            edit,
+            POTION_KINDS
+                .get_by_key(&repair.recipe.potion_kind_key)
+                .name(),
+            repair.recipe.overall_appeal,
+            repair.recipe.overall_potency
+        );
+    }
 }
 
 pub fn debug(config_filename: String) -> Result<(), Box<dyn std::error::Error>> {
@@ -25,25 +126,33 @@ pub fn debug(config_filename: String) -> Result<(), Box<dyn std::error::Error>>
     let config: Config = serde_yaml::from_str(&config_contents).unwrap();
     println!("{:?}", config);
 
-    let recipes: Vec<Recipe> = config
-        .recipes
-        .iter()
-        .filter_map(|recipe| {
-            let ingredients: Vec<Ingredient> = recipe
-                .iter()
-                .map(|(ingredient_key, ingredient_process)| {
-                    let raw_ingredient = INGREDIENTS.get_by_key(ingredient_key);
-                    process_ingredient(raw_ingredient, ingredient_process)
-                })
-                .collect();
-            let simulate_config = SimulateConfig {
-                alchemists_attributes: AlchemistAttributes::new(),
-                market_conditions: MarketConditions::new(),
-                branding_counts: BrandingCounts::new(),
-            };
-            simulate(ingredients.as_slice(), &simulate_config)
-        })
-        .collect();
+    let variants = match &config.process_rules {
+        Some(rules) => Arc::new(VariantTable::from_rules(rules)),
+        None => PROCESS_VARIANTS.clone(),
+    };
+
+    let simulate_config = SimulateConfig::default();
+    let repair_config = RepairConfig {
+        ingredients: INGREDIENTS.0.iter().map(|(key, _)| *key).collect(),
+        processes: ALL_PROCESSES.to_vec(),
+        repeated_ingredients: false,
+        variants: variants.clone(),
+    };
+
+    let mut recipes: Vec<Recipe> = Vec::new();
+    for recipe in &config.recipes {
+        for (ingredient_key, ingredient_process) in recipe {
+            if !ingredient_process.is_empty() {
+                let raw_ingredient = INGREDIENTS.get_by_key(ingredient_key);
+                display_trace(&variants.rules().trace(raw_ingredient, ingredient_process));
+            }
+        }
+        let ingredients = process_recipe(&variants, recipe);
+        match simulate(ingredients.as_slice(), &simulate_config) {
+            Some(recipe) => recipes.push(recipe),
+            None => display_repairs(&ingredients, &simulate_config, &repair_config),
+        }
+    }
 
     printer::print_recipes_table(&recipes);
     Ok(())
@@ -0,0 +1,50 @@
+use std::error::Error;
+
+use potionforge::{
+    models::{
+        traits::{GetByKey, GetName, ToHumanReadable},
+        IngredientKey, IngredientPart, INGREDIENTS,
+    },
+    process::{find_ingredients, find_process_chains, PartQuery},
+};
+
+use crate::catalogue::load_variants;
+
+pub fn find_part(
+    include: Vec<IngredientPart>,
+    exclude: Vec<IngredientPart>,
+    ingredient: Option<IngredientKey>,
+    rules: Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    let variants = load_variants(rules)?;
+    let query = PartQuery { include, exclude };
+
+    match ingredient {
+        Some(key) => {
+            let name = INGREDIENTS.get_by_key(&key).name();
+            let chains = find_process_chains(&variants, &key, &query);
+            if chains.is_empty() {
+                println!("No process chain of {} gives those parts", name);
+            }
+            for chain in chains {
+                println!("{} ({})", name, chain.to_human());
+            }
+        }
+        None => {
+            let ingredients = find_ingredients(&variants, &query);
+            if ingredients.is_empty() {
+                println!("No ingredient gives those parts");
+            }
+            for ingredient in ingredients {
+                println!(
+                    "{} ({}): {}",
+                    ingredient.name(),
+                    ingredient.process.to_human(),
+                    ingredient.parts.to_human()
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
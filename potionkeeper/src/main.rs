@@ -1,9 +1,35 @@
+use potionforge::models::{
+    traits::ToHumanReadable, Element, IngredientKey, IngredientPart, MainEffect, PotionKindKey,
+    Sweetness, Taste, Tastiness,
+};
+use recommend::ConfigFileOpt;
 use structopt::StructOpt;
 
+mod catalogue;
 mod debug;
+mod find_part;
 mod printer;
 mod recommend;
 
+const ALL_PARTS: [IngredientPart; 16] = [
+    IngredientPart::MainEffect(MainEffect::Cat),
+    IngredientPart::MainEffect(MainEffect::Bone),
+    IngredientPart::MainEffect(MainEffect::Soul),
+    IngredientPart::MainEffect(MainEffect::Beast),
+    IngredientPart::Element(Element::Fire),
+    IngredientPart::Element(Element::Aether),
+    IngredientPart::Element(Element::Water),
+    IngredientPart::Element(Element::Earth),
+    IngredientPart::Taste(Taste::Tastiness(Tastiness::Tasty)),
+    IngredientPart::Taste(Taste::Tastiness(Tastiness::Unsavory)),
+    IngredientPart::Taste(Taste::Sweetness(Sweetness::Bitter)),
+    IngredientPart::Taste(Taste::Sweetness(Sweetness::Sweet)),
+    IngredientPart::Stimulant,
+    IngredientPart::Impurity,
+    IngredientPart::Toxin,
+    IngredientPart::Antitoxin,
+];
+
 #[derive(StructOpt)]
 struct Opt {
     #[structopt(subcommand)]
@@ -13,25 +39,161 @@ struct Opt {
 #[derive(StructOpt)]
 enum Command {
     Recommend {
-        #[structopt(short, long, default_value = "recommend.yml")]
-        config: String,
+        #[structopt(flatten)]
+        config: ConfigFileOpt,
+        /// Always enumerate recipes instead of reusing cached results.
+        #[structopt(long)]
+        no_cache: bool,
+        /// Also report how much each recipe's scores and the market could
+        /// change before the recommendation changes.
+        #[structopt(long)]
+        sensitivity: bool,
+    },
+    /// Plan a line-up for each day listed in the recommend configuration.
+    Plan {
+        #[structopt(flatten)]
+        config: ConfigFileOpt,
+        /// Always enumerate recipes instead of reusing cached results.
+        #[structopt(long)]
+        no_cache: bool,
+    },
+    /// Explain why recipes listed in a debug configuration are not recommended.
+    ExplainExclusion {
+        #[structopt(flatten)]
+        config: ConfigFileOpt,
+        /// Debug configuration listing the recipes to explain.
+        #[structopt(short, long, default_value = "debug.yml")]
+        recipes: String,
+        /// Always enumerate recipes instead of reusing cached results.
+        #[structopt(long)]
+        no_cache: bool,
+    },
+    /// Suggest replacements for recipes of a debug configuration when some
+    /// ingredients are unavailable.
+    Substitute {
+        #[structopt(flatten)]
+        config: ConfigFileOpt,
+        /// Debug configuration listing the recipes to replace ingredients in.
+        #[structopt(short, long, default_value = "debug.yml")]
+        recipes: String,
+        #[structopt(short, long, parse(try_from_str = parse_ingredient))]
+        unavailable: Vec<IngredientKey>,
+        /// Most ingredients of a recipe to replace.
+        #[structopt(long, default_value = "2")]
+        swaps: usize,
+    },
+    /// List the process chains that give ingredients the parts wanted.
+    FindPart {
+        /// Parts wanted, such as Fire or Stimulant. Repeat a part to want it
+        /// more than once.
+        #[structopt(short, long, parse(try_from_str = parse_part))]
+        part: Vec<IngredientPart>,
+        /// Parts that must not appear, such as Toxin.
+        #[structopt(short, long, parse(try_from_str = parse_part))]
+        without: Vec<IngredientPart>,
+        /// Only search the chains of this ingredient.
+        #[structopt(short, long, parse(try_from_str = parse_ingredient))]
+        ingredient: Option<IngredientKey>,
+        /// Process rules file of a newer game release, instead of the built
+        /// in rules.
+        #[structopt(long)]
+        rules: Option<String>,
+    },
+    /// List every processed variant of the ingredients.
+    Catalogue {
+        /// Only list the variants of this ingredient.
+        #[structopt(short, long, parse(try_from_str = parse_ingredient))]
+        ingredient: Option<IngredientKey>,
+        /// Process rules file of a newer game release, instead of the built
+        /// in rules.
+        #[structopt(long)]
+        rules: Option<String>,
     },
     InitRecommend {
         #[structopt(short, long, default_value = "recommend.yml")]
         config: String,
     },
+    Best {
+        #[structopt(flatten)]
+        config: ConfigFileOpt,
+        #[structopt(short, long, parse(try_from_str = parse_potion_kind))]
+        potion: PotionKindKey,
+    },
+    /// Inspect the recommend configuration.
+    Config {
+        #[structopt(subcommand)]
+        cmd: ConfigCommand,
+    },
     Debug {
         #[structopt(short, long, default_value = "debug.yml")]
         config: String,
     },
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let opt = Opt::from_args();
+#[derive(StructOpt)]
+enum ConfigCommand {
+    /// Print the configuration with its includes, profile and overrides
+    /// merged.
+    Show {
+        #[structopt(flatten)]
+        config: ConfigFileOpt,
+    },
+}
+
+fn parse_potion_kind(potion: &str) -> Result<PotionKindKey, serde_yaml::Error> {
+    serde_yaml::from_str(potion)
+}
+
+fn parse_ingredient(ingredient: &str) -> Result<IngredientKey, serde_yaml::Error> {
+    serde_yaml::from_str(ingredient)
+}
+
+fn parse_part(part: &str) -> Result<IngredientPart, String> {
+    ALL_PARTS
+        .iter()
+        .find(|candidate| candidate.to_human().eq_ignore_ascii_case(part))
+        .copied()
+        .ok_or_else(|| format!("unknown part: {}", part))
+}
+
+fn main() {
+    if let Err(error) = run(Opt::from_args()) {
+        eprintln!("Error: {}", error);
+        std::process::exit(1);
+    }
+}
 
+fn run(opt: Opt) -> Result<(), Box<dyn std::error::Error>> {
     match opt.cmd {
         Command::InitRecommend { config } => recommend::init_recommend(config)?,
-        Command::Recommend { config } => recommend::recommend(config)?,
+        Command::Recommend {
+            config,
+            no_cache,
+            sensitivity,
+        } => recommend::recommend(config, !no_cache, sensitivity)?,
+        Command::Plan { config, no_cache } => recommend::plan(config, !no_cache)?,
+        Command::ExplainExclusion {
+            config,
+            recipes,
+            no_cache,
+        } => recommend::explain_exclusion(config, recipes, !no_cache)?,
+        Command::Substitute {
+            config,
+            recipes,
+            unavailable,
+            swaps,
+        } => recommend::substitute(config, recipes, unavailable, swaps)?,
+        Command::FindPart {
+            part,
+            without,
+            ingredient,
+            rules,
+        } => find_part::find_part(part, without, ingredient, rules)?,
+        Command::Catalogue { ingredient, rules } => catalogue::catalogue(ingredient, rules)?,
+        Command::Best { config, potion } => recommend::best(config, potion)?,
+        Command::Config {
+            cmd: ConfigCommand::Show { config },
+        } => recommend::show_config(config)?,
         Command::Debug { config } => debug::debug(config)?,
     }
 
@@ -4,20 +4,39 @@ use std::cmp::Ordering;
 
 use potionforge::models::{
     traits::{GetByKey, GetName, ToHumanReadable},
-    Ingredient, OverallPurity, OverallTaste, OverallToxicity, Recipe, TasteEffect, ToxicityEffect,
-    POTION_KINDS,
+    Ingredient, IngredientProcess, OverallPurity, OverallTaste, OverallToxicity, Recipe,
+    TasteEffect, ToxicityEffect, POTION_KINDS,
 };
 use prettytable::{Cell, Row, Table};
 
-fn get_ingredients_string(ingredients: &[Ingredient]) -> String {
+fn get_ingredients_string(
+    ingredients: &[Ingredient],
+    alternatives: &[Vec<IngredientProcess>],
+) -> String {
     let mut local_ingredients: Vec<String> = ingredients
         .iter()
-        .map(|ing| format!("{} ({})", ing.name(), ing.process.to_human()))
+        .zip(alternatives)
+        .map(|(ing, processes)| {
+            let processes: Vec<String> = processes.iter().map(|p| p.to_human()).collect();
+            format!("{} ({})", ing.name(), processes.join(" | "))
+        })
         .collect();
     local_ingredients.sort();
     local_ingredients.join(", ")
 }
 
+/// Short one line description of a recipe, for listings outside the table.
+pub fn recipe_summary(recipe: &Recipe) -> String {
+    let mut ingredients: Vec<&str> = recipe.ingredients.iter().map(|ing| ing.name()).collect();
+    ingredients.sort();
+    format!(
+        "{} [{}]: {}",
+        POTION_KINDS.get_by_key(&recipe.potion_kind_key).name(),
+        recipe.pricing.name(),
+        ingredients.join(", ")
+    )
+}
+
 fn get_toxicity_tag(
     toxicity_effect: &ToxicityEffect,
     overall_toxicity: &OverallToxicity,
@@ -96,7 +115,7 @@ fn get_purity_tag(overall_purity: &OverallPurity) -> &'static str {
 }
 
 fn add_recipe_row(table: &mut Table, index: usize, recipe: &Recipe) {
-    let ingredients = get_ingredients_string(&recipe.ingredients);
+    let ingredients = get_ingredients_string(&recipe.ingredients, &recipe.alternatives);
     let potion_kind = POTION_KINDS.get_by_key(&recipe.potion_kind_key);
     let toxicity_tag = get_toxicity_tag(&potion_kind.toxicity_effect, &recipe.overall_toxicity);
     let taste_tag = get_taste_tag(&potion_kind.taste_effect, &recipe.overall_taste);
@@ -112,6 +131,7 @@ fn add_recipe_row(table: &mut Table, index: usize, recipe: &Recipe) {
         Cell::new(&taste_tag),
         Cell::new(format!("{}%", recipe.overall_appeal).as_str()),
         Cell::new(format!("{:.1}", recipe.overall_potency as f64 / 100.).as_str()),
+        Cell::new(recipe.pricing.name()),
     ]));
 }
 
@@ -143,7 +163,11 @@ fn sort_recipes(recipes: &mut Vec<Recipe>) {
 pub fn print_recipes_table(recipes: &[Recipe]) {
     let mut sorted_recipes = recipes.to_vec();
     sort_recipes(&mut sorted_recipes);
+    print_ranked_recipes_table(&sorted_recipes);
+}
 
+/// Print recipes in the order given rather than grouped by department.
+pub fn print_ranked_recipes_table(recipes: &[Recipe]) {
     let mut table = create_table(vec![
         "Index",
         "Department",
@@ -154,9 +178,10 @@ pub fn print_recipes_table(recipes: &[Recipe]) {
         "Taste",
         "Appeal",
         "Potency",
+        "Pricing",
     ]);
 
-    for (i, recipe) in sorted_recipes.iter().enumerate() {
+    for (i, recipe) in recipes.iter().enumerate() {
         add_recipe_row(&mut table, i, recipe);
     }
     table.printstd();
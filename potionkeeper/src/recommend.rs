@@ -1,43 +1,75 @@
+use std::fs::File;
 use std::io::{copy, Cursor};
-use std::{fs::File, io::Read};
 
-use crate::printer;
+use crate::{debug, printer};
+use potionforge::cache::RecipeCache;
+use potionforge::config::{self, ConfigLayers, RecommendFile, EXAMPLE_CONFIG};
 use potionforge::core;
 use potionforge::enumerate::EnumerateConfig;
-use potionforge::simulate::SimulateConfig;
+use potionforge::explain::{self, Conflict, Explanation};
+use potionforge::plan;
+use potionforge::progress::{CancellationToken, IndicatifProgress};
+use potionforge::scenario;
+use potionforge::sensitivity::{self, ConditionChange, SensitivityReport};
+use potionforge::simulate;
+use potionforge::substitute::{self, SubstituteConfig};
 
-use serde::Deserialize;
+use structopt::StructOpt;
 
-use potionforge::models::{PotionKindKey, Process, Recipe};
-use potionforge::recommend::{
-    AlchemistAttributes, BrandingCounts, IngredientCounts, MarketConditions, RecommendConfig,
+use potionforge::models::traits::{GetByKey, GetName, ToHumanReadable};
+use potionforge::models::{
+    IngredientKey, PotionKindKey, Recipe, RecipeIntrinsics, INGREDIENTS, POTION_KINDS,
 };
 
-const EXAMPLE_CONFIG: &[u8] = include_bytes!("./recommend.yml.example");
+const BEST_RECIPE_LIMIT: usize = 10;
+const CACHE_DIRECTORY: &str = ".potionkeeper-cache";
+const SENSITIVITY_CANDIDATE_LIMIT: usize = 10;
+const SUBSTITUTION_LIMIT: usize = 10;
 
-#[derive(Debug, Deserialize)]
-struct _RecommendConfig {
-    arcane_power: i64,
-    utilisation: i32,
-    processes: Vec<Process>,
-    ingredients: IngredientCounts,
-    alchemists: AlchemistAttributes,
-    market: MarketConditions,
-    branding: BrandingCounts,
-    potions: Vec<PotionKindKey>,
+/// A recommend configuration file and the layers merged over it.
+#[derive(StructOpt)]
+pub struct ConfigFileOpt {
+    #[structopt(short, long, default_value = "recommend.yml")]
+    pub config: String,
+    /// Profile of the configuration file to merge over it.
+    #[structopt(long)]
+    pub profile: Option<String>,
+    /// Override a setting, such as arcane_power=4 or market.Speed=[HighDemand].
+    #[structopt(long = "set", number_of_values = 1)]
+    pub overrides: Vec<String>,
 }
 
-/// Load configuration from the specified file matching the Config struct.
-fn load_config(filename: String) -> Result<_RecommendConfig, Box<dyn std::error::Error>> {
-    let mut config_file = File::open(filename)?;
-    let mut config_contents = String::new();
-    config_file.read_to_string(&mut config_contents)?;
-    Ok(serde_yaml::from_str(&config_contents)?)
+impl ConfigFileOpt {
+    fn layers(&self) -> ConfigLayers {
+        ConfigLayers {
+            profile: self.profile.clone(),
+            overrides: self.overrides.clone(),
+        }
+    }
+
+    fn load(&self) -> Result<RecommendFile, Box<dyn std::error::Error>> {
+        RecommendFile::load_layered(&self.config, &self.layers())
+    }
+}
+
+/// A token cancelled by Ctrl-C, so a long enumeration or solve can be
+/// stopped cleanly. A second Ctrl-C exits straight away.
+fn cancel_on_interrupt() -> Result<CancellationToken, Box<dyn std::error::Error>> {
+    let cancel = CancellationToken::new();
+    let handler_cancel = cancel.clone();
+    ctrlc::set_handler(move || {
+        if handler_cancel.is_cancelled() {
+            std::process::exit(130);
+        }
+        eprintln!("Cancelling...");
+        handler_cancel.cancel();
+    })?;
+    Ok(cancel)
 }
 
 fn write_example_config(filename: String) -> Result<(), Box<dyn std::error::Error>> {
     let mut config_file = File::create(filename)?;
-    let mut cursor = Cursor::new(EXAMPLE_CONFIG);
+    let mut cursor = Cursor::new(EXAMPLE_CONFIG.as_bytes());
 
     copy(&mut cursor, &mut config_file)?;
 
@@ -59,9 +91,109 @@ fn display_results(recommendations: &[Recipe]) {
 
     println!("Total Appeal: {}", total_appeal);
     println!("Total Potency: {}", total_potency);
+    if recommendations
+        .iter()
+        .all(|recipe| recipe.revenue.is_some())
+    {
+        let total_revenue: i32 = recommendations
+            .iter()
+            .filter_map(|recipe| recipe.revenue)
+            .sum();
+        println!("Total Revenue: {}", total_revenue);
+    }
     printer::print_recipes_table(&recommendations);
 }
 
+fn display_margin(margin: Option<i32>) -> String {
+    match margin {
+        Some(margin) => margin.to_string(),
+        None => "-".to_string(),
+    }
+}
+
+fn display_sensitivity(report: &SensitivityReport) {
+    println!("Margin before leaving the recommendation (appeal / potency):");
+    for item in &report.selected {
+        println!(
+            "  {} / {}  {}",
+            display_margin(item.appeal_margin),
+            display_margin(item.potency_margin),
+            printer::recipe_summary(&item.recipe)
+        );
+    }
+    println!("Gain needed to enter the recommendation (appeal / potency):");
+    for item in &report.unselected {
+        println!(
+            "  {} / {}  {}",
+            display_margin(item.appeal_margin),
+            display_margin(item.potency_margin),
+            printer::recipe_summary(&item.recipe)
+        );
+    }
+    println!("Changes that alter the recommendation:");
+    for flip in &report.flips {
+        let change = match &flip.change {
+            ConditionChange::AddMarket(key, condition) => format!(
+                "{} becomes {:?}",
+                POTION_KINDS.get_by_key(key).name(),
+                condition
+            ),
+            ConditionChange::RemoveMarket(key, condition) => format!(
+                "{} no longer {:?}",
+                POTION_KINDS.get_by_key(key).name(),
+                condition
+            ),
+            ConditionChange::Branding(category, delta) => {
+                format!("{:?} branding {:+}", category, delta)
+            }
+        };
+        println!("  {}", change);
+        for recipe in &flip.leaving {
+            println!("    - {}", printer::recipe_summary(recipe));
+        }
+        for recipe in &flip.entering {
+            println!("    + {}", printer::recipe_summary(recipe));
+        }
+    }
+}
+
+/// Enumerate the recipe intrinsics for the configuration, reusing the cached
+/// results of an earlier run when allowed.
+fn load_or_enumerate(
+    enumerate_config: &EnumerateConfig,
+    use_cache: bool,
+    progress: &IndicatifProgress,
+    cancel: &CancellationToken,
+) -> Result<Vec<RecipeIntrinsics>, Box<dyn std::error::Error>> {
+    let cache = RecipeCache::new(CACHE_DIRECTORY);
+    let cache_key = RecipeCache::key(enumerate_config);
+
+    let cached_intrinsics = if use_cache {
+        cache.load(&cache_key)
+    } else {
+        None
+    };
+
+    match cached_intrinsics {
+        Some(intrinsics) => {
+            println!("Cache hit: {}", cache_key);
+            Ok(intrinsics)
+        }
+        None => {
+            if use_cache {
+                println!("Cache miss: {}", cache_key);
+            }
+            println!("Enumerating possible recipes...");
+            let intrinsics = core::enumerate(enumerate_config, progress, cancel)?;
+            // The recipes are still usable when the cache cannot be written.
+            if let Err(error) = cache.store(&cache_key, &intrinsics) {
+                eprintln!("Warning: could not cache recipes: {}", error);
+            }
+            Ok(intrinsics)
+        }
+    }
+}
+
 /// Recommend potions that can be created using the provided configuration
 ///
 /// 1. Read configuration file specified and extract available ingredients,
@@ -71,45 +203,360 @@ fn display_results(recommendations: &[Recipe]) {
 /// ingredients.
 ///
 /// 3. Recommend a combination of recipes using the potionforge algorithm.
-pub fn recommend(config_filename: String) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Recommend using config file: {}", config_filename);
-    let config = load_config(config_filename)?;
+pub fn recommend(
+    config_file: ConfigFileOpt,
+    use_cache: bool,
+    sensitivity: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Recommend using config file: {}", config_file.config);
+    let config = config_file.load()?;
 
-    let ingredient_keys: Vec<_> = config.ingredients.keys().map(|key| key.clone()).collect();
+    let enumerate_config = config.enumerate_config();
+    let simulate_config = config.simulate_config();
+    let recommend_config = config.recommend_config();
 
-    let enumerate_config = EnumerateConfig {
-        ingredients: ingredient_keys,
-        arcane_power: config.arcane_power,
-        utilisation: config.utilisation,
-        processes: config.processes,
-    };
+    let progress = IndicatifProgress::new();
+    let cancel = cancel_on_interrupt()?;
+    let intrinsics = load_or_enumerate(&enumerate_config, use_cache, &progress, &cancel)?;
 
-    let simulate_config = SimulateConfig {
-        alchemists_attributes: config.alchemists,
-        market_conditions: config.market,
-        branding_counts: config.branding,
-    };
+    if !config.scenarios.is_empty() {
+        println!(
+            "Recommending optimal recipes over {} market scenarios...",
+            config.scenarios.len()
+        );
+        let recommendation = scenario::recommend_scenarios(
+            &intrinsics,
+            &simulate_config,
+            &enumerate_config.filters,
+            &config.scenarios,
+            config.scenario_objective,
+            &recommend_config,
+            &cancel,
+        )?;
 
-    let recommend_config = RecommendConfig {
-        available_ingredients: config.ingredients,
-        utilisation: config.utilisation,
-        potions: config.potions,
-    };
+        let total_probability: f64 = config
+            .scenarios
+            .iter()
+            .map(|scenario| scenario.probability)
+            .sum();
+        println!("Expected Appeal: {:.1}", recommendation.expected_appeal);
+        for (scenario, appeal) in config
+            .scenarios
+            .iter()
+            .zip(&recommendation.scenario_appeals)
+        {
+            println!(
+                "Appeal if {} ({:.0}%): {}",
+                scenario.name,
+                100. * scenario.probability / total_probability,
+                appeal
+            );
+        }
+        display_results(&recommendation.recipes);
+        return Ok(());
+    }
 
-    println!("Enumerating possible recipes...");
     let possible_recipes: Vec<Recipe> =
-        core::enumerate_and_simulate(&enumerate_config, &simulate_config);
+        core::score_recipes(&intrinsics, &simulate_config, &enumerate_config.filters);
 
     println!("Got {} possible recipes.", possible_recipes.len());
+    let (possible_recipes, pruned) = core::prune_dominated(possible_recipes);
+    println!("Pruned {} dominated recipes.", pruned);
     println!("Recommending optimal recipes...");
-    let recommendations: Vec<Recipe> = core::recommend(possible_recipes, &recommend_config);
+    let recommendations: Vec<Recipe> =
+        core::recommend(possible_recipes, &recommend_config, &progress, &cancel)?;
+
+    if sensitivity {
+        println!("Analysing sensitivity...");
+        let report = sensitivity::analyse_sensitivity(
+            &intrinsics,
+            &simulate_config,
+            &enumerate_config.filters,
+            &recommend_config,
+            &recommendations,
+            SENSITIVITY_CANDIDATE_LIMIT,
+            &cancel,
+        )?;
+        display_sensitivity(&report);
+    }
 
     display_results(&recommendations);
     Ok(())
 }
 
+/// Plan a line-up for each day listed in the configuration file, carrying the
+/// remaining ingredients over to the next day.
+pub fn plan(config_file: ConfigFileOpt, use_cache: bool) -> Result<(), Box<dyn std::error::Error>> {
+    println!("Plan using config file: {}", config_file.config);
+    let config = config_file.load()?;
+
+    if config.days.is_empty() {
+        return Err("the configuration file does not list any days".into());
+    }
+
+    // Restocked ingredients can be used too, once they have arrived.
+    let mut ingredient_keys: Vec<_> = config
+        .ingredients
+        .keys()
+        .chain(config.days.iter().flat_map(|day| day.restock.keys()))
+        .cloned()
+        .collect();
+    ingredient_keys.sort();
+    ingredient_keys.dedup();
+
+    let mut enumerate_config = config.enumerate_config();
+    enumerate_config.ingredients = ingredient_keys;
+
+    let plan_config = config.plan_config();
+    let simulate_config = config.simulate_config();
+
+    let intrinsics = load_or_enumerate(
+        &enumerate_config,
+        use_cache,
+        &IndicatifProgress::new(),
+        &cancel_on_interrupt()?,
+    )?;
+
+    println!("Planning {} days...", plan_config.days.len());
+    let lineups = plan::plan(
+        &intrinsics,
+        &simulate_config,
+        &enumerate_config.filters,
+        &plan_config,
+    );
+
+    for (day, recipes) in lineups.iter().enumerate() {
+        println!("Day {}", day + 1);
+        display_results(recipes);
+    }
+    Ok(())
+}
+
+/// Explain why each recipe of a debug configuration is missing from the
+/// recommendation for the recommend configuration.
+pub fn explain_exclusion(
+    config_file: ConfigFileOpt,
+    recipes_filename: String,
+    use_cache: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!(
+        "Explain recipes in {} using config file: {}",
+        recipes_filename, config_file.config
+    );
+    let config = config_file.load()?;
+    let enumerate_config = config.enumerate_config();
+    let recipes = debug::load_recipes(&recipes_filename, &enumerate_config.variants)?;
+
+    let simulate_config = config.simulate_config();
+    let recommend_config = config.recommend_config();
+
+    let cancel = cancel_on_interrupt()?;
+    let intrinsics = load_or_enumerate(
+        &enumerate_config,
+        use_cache,
+        &IndicatifProgress::new(),
+        &cancel,
+    )?;
+
+    for ingredients in recipes {
+        let names: Vec<String> = ingredients
+            .iter()
+            .map(|ingredient| ingredient.to_human())
+            .collect();
+        println!("{}", names.join(", "));
+
+        let explanation = explain::explain_exclusion(
+            &ingredients,
+            &intrinsics,
+            &simulate_config,
+            &recommend_config,
+            &enumerate_config,
+            &cancel,
+        )?;
+        display_explanation(&explanation);
+    }
+    Ok(())
+}
+
+fn display_explanation(explanation: &Explanation) {
+    let conflicts = match explanation {
+        Explanation::Rejected(rejection) => {
+            println!("  Never considered: {:?}", rejection);
+            return;
+        }
+        Explanation::Selected(recipe) => {
+            println!("  Recommended as {}", printer::recipe_summary(recipe));
+            return;
+        }
+        Explanation::Excluded {
+            recipe,
+            conflicts,
+            loss,
+            ..
+        } => {
+            println!("  Excluded: {}", printer::recipe_summary(recipe));
+            println!(
+                "  Forcing it in loses {} recipes, {} appeal and {} potency",
+                loss.recipes, loss.appeal, loss.potency
+            );
+            conflicts
+        }
+        Explanation::Infeasible { recipe, conflicts } => {
+            println!(
+                "  Cannot be recommended: {}",
+                printer::recipe_summary(recipe)
+            );
+            conflicts
+        }
+    };
+
+    for conflict in conflicts {
+        match conflict {
+            Conflict::Ingredient {
+                key,
+                available,
+                used_by,
+            } => {
+                println!(
+                    "  Conflicts over {} ({} available) with:",
+                    INGREDIENTS.get_by_key(key).name(),
+                    available
+                );
+                for recipe in used_by {
+                    println!("    {}", printer::recipe_summary(recipe));
+                }
+            }
+            Conflict::PotionKind { recommended } => println!(
+                "  Takes the potion kind slot of {}",
+                printer::recipe_summary(recommended)
+            ),
+            Conflict::Department {
+                department,
+                recipes,
+            } => println!(
+                "  {:?} department already has {} recipes",
+                department,
+                recipes.len()
+            ),
+        }
+    }
+}
+
+/// Suggest the closest replacements for each recipe of a debug configuration
+/// without the unavailable ingredients, drawing on the ingredients and
+/// processes of the recommend configuration.
+pub fn substitute(
+    config_file: ConfigFileOpt,
+    recipes_filename: String,
+    unavailable: Vec<IngredientKey>,
+    max_swaps: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!(
+        "Substitute in recipes of {} using config file: {}",
+        recipes_filename, config_file.config
+    );
+    let config = config_file.load()?;
+    let variants = config.variants();
+    let recipes = debug::load_recipes(&recipes_filename, &variants)?;
+
+    let simulate_config = config.simulate_config();
+
+    let substitute_config = SubstituteConfig {
+        ingredients: config.ingredients.keys().cloned().collect(),
+        variants,
+        processes: config.processes,
+        max_swaps,
+        repeated_ingredients: config.repeated_ingredients,
+        filters: config.filters,
+    };
+
+    for ingredients in recipes {
+        let names: Vec<String> = ingredients
+            .iter()
+            .map(|ingredient| ingredient.to_human())
+            .collect();
+        println!("{}", names.join(", "));
+
+        let recipe = match simulate::simulate(&ingredients, &simulate_config) {
+            Some(recipe) => recipe,
+            None => {
+                println!("  Not a potion");
+                continue;
+            }
+        };
+        let substitutions = substitute::suggest_substitutions(
+            &recipe,
+            &unavailable,
+            &simulate_config,
+            &substitute_config,
+        );
+        if substitutions.is_empty() {
+            println!("  No substitutes found");
+        }
+        for substitution in substitutions.iter().take(SUBSTITUTION_LIMIT) {
+            let replaced: Vec<String> = substitution
+                .replaced
+                .iter()
+                .map(|ingredient| ingredient.to_human())
+                .collect();
+            let ingredients: Vec<String> = substitution
+                .recipe
+                .ingredients
+                .iter()
+                .map(|ingredient| ingredient.to_human())
+                .collect();
+            println!(
+                "  {:+} appeal, {:+} potency: {} (replacing {})",
+                substitution.appeal_difference,
+                substitution.potency_difference,
+                ingredients.join(", "),
+                replaced.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
 pub(crate) fn init_recommend(config: String) -> Result<(), Box<dyn std::error::Error>> {
     write_example_config(config)?;
 
     Ok(())
 }
+
+/// Show the best recipes for a single potion kind using the ingredients,
+/// processes and market of the recommend configuration file.
+pub fn best(
+    config_file: ConfigFileOpt,
+    potion: PotionKindKey,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!(
+        "Best {:?} recipes using config file: {}",
+        potion, config_file.config
+    );
+    let config = config_file.load()?;
+
+    let enumerate_config = config.enumerate_config();
+    let simulate_config = config.simulate_config();
+
+    let recipes = core::enumerate_and_simulate_potion(
+        &potion,
+        &enumerate_config,
+        &simulate_config,
+        &IndicatifProgress::new(),
+        &cancel_on_interrupt()?,
+    )?;
+
+    println!("Got {} possible recipes.", recipes.len());
+    printer::print_ranked_recipes_table(&recipes[..recipes.len().min(BEST_RECIPE_LIMIT)]);
+    Ok(())
+}
+
+/// Print the effective configuration after merging the includes, profile and
+/// overrides, then check that it is valid.
+pub fn show_config(config_file: ConfigFileOpt) -> Result<(), Box<dyn std::error::Error>> {
+    let merged = config::merge_layers(&config_file.config, &config_file.layers())?;
+    println!("{}", serde_yaml::to_string(&merged)?);
+    config_file.load()?;
+
+    Ok(())
+}
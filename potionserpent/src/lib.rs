@@ -1,6 +1,7 @@
 mod models;
 
 use models::*;
+use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
 
 #[pyfunction]
@@ -14,6 +15,38 @@ fn get_ingredients() -> PyResult<Vec<Ingredient>> {
     Ok(ingredients)
 }
 
+/// Every processed variant of every ingredient, under the process rules file
+/// of a newer game release if one is given, raising a `ValueError` if it does
+/// not load.
+#[pyfunction]
+#[pyo3(signature = (rules = None))]
+fn get_ingredient_variants(rules: Option<&str>) -> PyResult<Vec<Ingredient>> {
+    let table = match rules {
+        Some(path) => {
+            let rules = ::potionforge::process::ProcessRules::load(path)
+                .map_err(|error| PyValueError::new_err(error.to_string()))?;
+            std::sync::Arc::new(::potionforge::process::VariantTable::from_rules(&rules))
+        }
+        None => ::potionforge::process::PROCESS_VARIANTS.clone(),
+    };
+    let variants: Vec<_> = table
+        .iter()
+        .map(|ingredient| Ingredient::from(ingredient.clone()))
+        .collect();
+
+    Ok(variants)
+}
+
+/// Check a recommend configuration file and its includes, raising a `ValueError` with the line
+/// and column of the first problem.
+#[pyfunction]
+fn validate_config(path: &str) -> PyResult<()> {
+    ::potionforge::config::RecommendFile::load(path)
+        .map_err(|error| PyValueError::new_err(error.to_string()))?;
+
+    Ok(())
+}
+
 /// A Python module implemented in Rust. The name of this function must match
 /// the `lib.name` setting in the `Cargo.toml`, else Python will not be able to
 /// import the module.
@@ -21,5 +54,7 @@ fn get_ingredients() -> PyResult<Vec<Ingredient>> {
 fn potionforge(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_class::<IngredientKey>()?;
     m.add_function(wrap_pyfunction!(get_ingredients, m)?)?;
+    m.add_function(wrap_pyfunction!(get_ingredient_variants, m)?)?;
+    m.add_function(wrap_pyfunction!(validate_config, m)?)?;
     Ok(())
 }